@@ -0,0 +1,322 @@
+use super::*;
+
+/// The output of a task that picks one of two branches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Either<L, R> {
+    Left(L),
+    Right(R),
+}
+
+/// Decides whether an output goes down a channel. The output is passed as `&dyn Any`,
+/// since the producer doesn't know the type its consumers matched it against.
+pub(crate) type Route = fn(&dyn Any) -> bool;
+
+/// One of the variants of a branching task's output.
+pub trait Variant<O>: 'static {
+    type Item;
+    fn pick(output: &O) -> Option<&Self::Item>;
+    fn take(output: O) -> Option<Self::Item>;
+}
+#[derive(Debug)]
+pub struct LeftArm;
+#[derive(Debug)]
+pub struct RightArm;
+#[derive(Debug)]
+pub struct SomeArm;
+impl<L: 'static, R: 'static> Variant<Either<L, R>> for LeftArm {
+    type Item = L;
+    fn pick(output: &Either<L, R>) -> Option<&L> {
+        match output {
+            Either::Left(l) => Some(l),
+            Either::Right(_) => None,
+        }
+    }
+    fn take(output: Either<L, R>) -> Option<L> {
+        match output {
+            Either::Left(l) => Some(l),
+            Either::Right(_) => None,
+        }
+    }
+}
+impl<L: 'static, R: 'static> Variant<Either<L, R>> for RightArm {
+    type Item = R;
+    fn pick(output: &Either<L, R>) -> Option<&R> {
+        match output {
+            Either::Left(_) => None,
+            Either::Right(r) => Some(r),
+        }
+    }
+    fn take(output: Either<L, R>) -> Option<R> {
+        match output {
+            Either::Left(_) => None,
+            Either::Right(r) => Some(r),
+        }
+    }
+}
+impl<T: 'static> Variant<Option<T>> for SomeArm {
+    type Item = T;
+    fn pick(output: &Option<T>) -> Option<&T> {
+        output.as_ref()
+    }
+    fn take(output: Option<T>) -> Option<T> {
+        output
+    }
+}
+
+/// A channel input that only receives the outputs of a branching task that match one
+/// variant, unwrapped. Built by [`TaskHandle::left`], [`TaskHandle::right`] and
+/// [`TaskHandle::some`]. When the producer picks another variant the consumer is held,
+/// and so is everything downstream of it that takes its output - none of them run, or
+/// take their resource leases.
+#[derive(Debug)]
+pub struct Arm<O, V> {
+    handle: TaskHandle<O>,
+    _marker: PhantomData<V>,
+}
+impl<O, V> Arm<O, V> {
+    fn new(handle: TaskHandle<O>) -> Self {
+        Self {
+            handle,
+            _marker: PhantomData,
+        }
+    }
+}
+impl<L, R> TaskHandle<Either<L, R>> {
+    pub fn left(self) -> Arm<Either<L, R>, LeftArm> {
+        Arm::new(self)
+    }
+    pub fn right(self) -> Arm<Either<L, R>, RightArm> {
+        Arm::new(self)
+    }
+}
+impl<T> TaskHandle<Option<T>> {
+    pub fn some(self) -> Arm<Option<T>, SomeArm> {
+        Arm::new(self)
+    }
+}
+
+fn route<O: 'static, V: Variant<O>>(output: &dyn Any) -> bool {
+    output
+        .downcast_ref::<O>()
+        .is_some_and(|output| V::pick(output).is_some())
+}
+
+/// Takes the matching output by value, like a [`TaskHandle`]: the last consumer moves
+/// it, and any others get a clone of the variant.
+impl<O, V> Args for Arm<O, V>
+where
+    O: Send + Sync + 'static,
+    V: Variant<O>,
+    V::Item: Clone,
+{
+    type Data<'a> = V::Item;
+    type Receivers = Receiver<Arc<O>>;
+    fn get_edge_info(&self) -> Vec<(NodeHandle, Edge)> {
+        vec![(
+            self.handle.into(),
+            Edge {
+                arg_idx: 0,
+                meta: Access::Consume,
+                channel: None,
+                route: Some(route::<O, V>),
+            },
+        )]
+    }
+    fn prepare_inputs<'a>(
+        state: &Self::Receivers,
+        ctx: &mut RwGuards<'a>,
+    ) -> Result<Self::Data<'a>, ReceiveError> {
+        let output = receive(state)?;
+        let item = match Arc::try_unwrap(output) {
+            Ok(output) => V::take(output),
+            Err(output) => V::pick(&output).cloned(),
+        };
+        Ok(item.expect("Arms are only sent outputs they match"))
+    }
+}
+
+/// Joins two channel inputs where only one is expected to have a value - typically the
+/// ends of two arms of a branch. The task runs when either has one, and sees which it
+/// was. If both do, the left one is taken first.
+#[derive(Debug)]
+pub struct Join<A, B>(pub A, pub B);
+
+/// The receivers of a [`Join`], ready when either side is.
+pub struct JoinReceivers<A, B>(A, B);
+impl<A: ArgsState, B: ArgsState> ArgsState for JoinReceivers<A, B> {
+    fn downcast(receivers: &mut dyn Iterator<Item = Box<dyn Any>>) -> Self {
+        let a = A::downcast(receivers);
+        JoinReceivers(a, B::downcast(receivers))
+    }
+    fn ready(&self) -> bool {
+        self.0.ready() || self.1.ready()
+    }
+    fn closed(&self) -> bool {
+        self.0.closed() && self.1.closed()
+    }
+    fn skip(&self) {
+        if self.0.ready() {
+            self.0.skip()
+        } else {
+            self.1.skip()
+        }
+    }
+}
+
+fn channel_input(edges: &[(NodeHandle, Edge)]) -> bool {
+    matches!(edges, [(_, edge)] if matches!(edge.meta, Access::Consume | Access::Share))
+}
+
+impl<A: Args, B: Args> Args for Join<A, B> {
+    type Data<'a> = Either<A::Data<'a>, B::Data<'a>>;
+    type Receivers = JoinReceivers<A::Receivers, B::Receivers>;
+    fn get_edge_info(&self) -> Vec<(NodeHandle, Edge)> {
+        let mut edges = self.0.get_edge_info();
+        let mut right = self.1.get_edge_info();
+        // Either side may be missing, so neither can hold a lease in argument order.
+        assert!(
+            channel_input(&edges) && channel_input(&right),
+            "Only single channel inputs can be joined"
+        );
+        right[0].1.arg_idx = 1;
+        edges.append(&mut right);
+        edges
+    }
+    fn prepare_inputs<'a>(
+        state: &Self::Receivers,
+        ctx: &mut RwGuards<'a>,
+    ) -> Result<Self::Data<'a>, ReceiveError> {
+        if state.0.ready() {
+            A::prepare_inputs(&state.0, ctx).map(Either::Left)
+        } else {
+            B::prepare_inputs(&state.1, ctx).map(Either::Right)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn test_option_skips_downstream() {
+        let mut graph = Executor::new();
+        let positions = graph.add_resource(vec![0.0f32, 5.0, 5.5]);
+        let velocities = graph.add_resource(vec![1.0f32; 3]);
+        let detect_collisions = graph.add_task(Read(positions), |positions| {
+            let pairs: Vec<_> = (0..positions.len())
+                .flat_map(|i| (i + 1..positions.len()).map(move |j| (i, j)))
+                .filter(|&(i, j)| (positions[i] - positions[j]).abs() < 1.0)
+                .collect();
+            (!pairs.is_empty()).then_some(pairs)
+        });
+        let responses = Arc::new(AtomicUsize::new(0));
+        let counter = responses.clone();
+        let respond = graph.add_task(
+            (detect_collisions.some(), Write(velocities)),
+            move |(pairs, mut velocities)| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                for (i, j) in pairs {
+                    velocities[i] = -velocities[i];
+                    velocities[j] = -velocities[j];
+                }
+                velocities.len()
+            },
+        );
+        let downstream = Arc::new(AtomicUsize::new(0));
+        let counter = downstream.clone();
+        graph.add_task(respond, move |_| counter.fetch_add(1, Ordering::SeqCst));
+
+        graph.execute().unwrap();
+        assert_eq!(*graph.get(velocities).unwrap(), [1.0, -1.0, -1.0]);
+        graph.execute_parallel().unwrap();
+        assert_eq!(responses.load(Ordering::SeqCst), 2);
+        assert_eq!(downstream.load(Ordering::SeqCst), 2);
+
+        graph.get_mut(positions).unwrap()[2] = 9.0;
+        graph.execute().unwrap();
+        graph.execute_parallel().unwrap();
+        assert_eq!(*graph.get(velocities).unwrap(), [1.0; 3]);
+        assert_eq!(responses.load(Ordering::SeqCst), 2);
+        assert_eq!(downstream.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_join_sees_arm() {
+        let mut graph = Executor::new();
+        let value = graph.add_resource(0);
+        let classify = graph.add_task(Read(value), |v| match *v % 2 {
+            0 => Either::Left(*v),
+            _ => Either::Right(format!("odd {}", *v)),
+        });
+        let even = graph.add_task(classify.left(), |v| v * 10);
+        let odd = graph.add_task(classify.right(), |s: String| s.len());
+        let log = graph.add_resource(vec![]);
+        graph.add_task((Join(even, odd), Write(log)), |(arm, mut log)| {
+            log.push(arm)
+        });
+        for v in 1..=4 {
+            *graph.get_mut(value).unwrap() = v;
+            if v % 2 == 0 {
+                graph.execute().unwrap();
+            } else {
+                graph.execute_parallel().unwrap();
+            }
+        }
+        assert_eq!(
+            *graph.get(log).unwrap(),
+            [
+                Either::Right(5),
+                Either::Left(20),
+                Either::Right(5),
+                Either::Left(40)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pairs_stay_aligned_across_runs() {
+        let mut graph = Executor::new();
+        let value = graph.add_resource(0);
+        let a = graph.add_task(Read(value), |v| *v);
+        let b = graph.add_task(Read(value), |v| (*v % 2 == 1).then_some(*v));
+        let pairs = graph.add_resource(vec![]);
+        graph.add_task((a, b.some(), Write(pairs)), |(a, b, mut pairs)| {
+            pairs.push((a, b))
+        });
+        for v in 1..=6 {
+            *graph.get_mut(value).unwrap() = v;
+            if v % 4 < 2 {
+                graph.execute().unwrap();
+            } else {
+                graph.execute_parallel().unwrap();
+            }
+        }
+        assert_eq!(*graph.get(pairs).unwrap(), [(1, 1), (3, 3), (5, 5)]);
+    }
+
+    #[test]
+    fn test_skipped_tasks_take_no_leases() {
+        let mut graph = Executor::new();
+        graph.enable_tracing();
+        let shared = graph.add_resource(0);
+        let branch = graph.add_task((), |()| None::<i32>);
+        let skipped = graph.add_task((branch.some(), Write(shared)), |(v, mut s)| *s = v);
+        graph.execute_parallel().unwrap();
+        assert!(graph
+            .spans()
+            .iter()
+            .all(|span| span.task != skipped.idx || span.kind != SpanKind::Wait));
+        assert_eq!(*graph.get(shared).unwrap(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_join_rejects_resources() {
+        let mut graph = Executor::new();
+        let value = graph.add_resource(0);
+        let task = graph.add_task((), |()| 1);
+        graph.add_task(Join(task, Read(value)), |_| ());
+    }
+}
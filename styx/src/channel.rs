@@ -0,0 +1,182 @@
+use super::*;
+
+/// What a task does with an output when the channel to a consumer is already full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// Hold the producer back. The scheduler doesn't run it again until the consumer has
+    /// caught up, but everything that can still run does.
+    #[default]
+    Block,
+    /// Drop the oldest queued value to make room.
+    DropOldest,
+    /// Drop the new value.
+    DropNewest,
+    /// Fail the producer with [`ExecutionError::ChannelFull`] instead of running it.
+    Error,
+}
+
+/// How many outputs a channel between two tasks holds, and what happens once it's full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Channel {
+    pub capacity: usize,
+    pub overflow: Overflow,
+}
+impl Default for Channel {
+    fn default() -> Self {
+        Self {
+            capacity: 10,
+            overflow: Overflow::Block,
+        }
+    }
+}
+
+/// A channel input - a [`TaskHandle`] or [`Shared`] - with its own [`Channel`] settings.
+/// Unlike plain channel inputs, outputs queued in it are kept when the consumer doesn't
+/// run, so a slow consumer can fall behind by up to `capacity` values.
+#[derive(Debug)]
+pub struct Buffered<I>(pub I, pub Channel);
+
+impl<I: Args> Args for Buffered<I> {
+    type Data<'a> = I::Data<'a>;
+    type Receivers = I::Receivers;
+    fn get_edge_info(&self) -> Vec<(NodeHandle, Edge)> {
+        assert!(
+            self.1.capacity > 0,
+            "A channel has to hold at least one value"
+        );
+        let mut edges = self.0.get_edge_info();
+        let [(_, edge)] = &mut edges[..] else {
+            panic!("Only a single channel input can be buffered");
+        };
+        assert!(
+            matches!(edge.meta, Access::Consume | Access::Share),
+            "Only a single channel input can be buffered"
+        );
+        edge.channel = Some(self.1);
+        edges
+    }
+    fn prepare_inputs<'a>(
+        state: &Self::Receivers,
+        ctx: &mut RwGuards<'a>,
+    ) -> Result<Self::Data<'a>, ReceiveError> {
+        I::prepare_inputs(state, ctx)
+    }
+}
+
+/// The sending half of one of a task's output channels.
+pub(crate) struct Outgoing<O> {
+    pub(crate) sender: Sender<O>,
+    /// A second receiver, used to discard unconsumed outputs and make room under
+    /// [`Overflow::DropOldest`].
+    pub(crate) drain: Receiver<O>,
+    pub(crate) consumer: NodeIndex,
+    /// Whether the consumer takes the output by value, see [`Slot`].
+    pub(crate) by_value: bool,
+    pub(crate) overflow: Overflow,
+    /// Whether the channel was set up with [`Buffered`].
+    pub(crate) buffered: bool,
+    pub(crate) route: Option<Route>,
+}
+impl<O> Outgoing<O> {
+    pub(crate) fn send(&self, value: O) {
+        match self.overflow {
+            Overflow::Block | Overflow::Error => {
+                self.sender.send(value).ok();
+            }
+            Overflow::DropNewest => {
+                self.sender.try_send(value).ok();
+            }
+            Overflow::DropOldest => {
+                let mut value = Some(value);
+                while let Ok(false) = self.sender.try_send_option(&mut value) {
+                    self.drain.try_recv().ok();
+                }
+            }
+        }
+    }
+    /// Whether `output` goes down this channel, rather than to another arm of a branch.
+    pub(crate) fn routes(&self, output: &dyn Any) -> bool {
+        self.route.is_none_or(|route| route(output))
+    }
+    /// Full, and set up to hold the producer back rather than drop anything.
+    pub(crate) fn backpressured(&self) -> bool {
+        self.overflow == Overflow::Block && self.sender.is_full()
+    }
+    pub(crate) fn rejects(&self) -> bool {
+        self.overflow == Overflow::Error && self.sender.is_full()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    };
+
+    /// A counter that feeds a buffered consumer and an unbuffered one. Only the unbuffered
+    /// consumer runs under `execute_until`, so the buffered one falls behind.
+    fn build(overflow: Overflow) -> (Executor, TaskHandle<usize>, Arc<Mutex<Vec<usize>>>) {
+        let mut graph = Executor::new();
+        let count = Arc::new(AtomicUsize::new(0));
+        let seen = Arc::new(Mutex::new(vec![]));
+        let counter = graph.add_task((), move |_| count.fetch_add(1, Ordering::SeqCst) + 1);
+        let channel = Channel {
+            capacity: 2,
+            overflow,
+        };
+        let store = seen.clone();
+        graph.add_task(Buffered(counter, channel), move |n| {
+            store.lock().unwrap().push(n)
+        });
+        let latest = graph.add_task(counter, |n| n);
+        (graph, latest, seen)
+    }
+
+    #[test]
+    fn test_overflow_drops() {
+        for (overflow, expected) in [
+            (Overflow::DropOldest, vec![5, 6, 7]),
+            (Overflow::DropNewest, vec![1, 2, 7]),
+        ] {
+            let (mut graph, latest, seen) = build(overflow);
+            for i in 1..=5 {
+                assert_eq!(graph.execute_until(latest).unwrap(), i);
+            }
+            graph.execute().unwrap();
+            graph.execute_parallel().unwrap();
+            graph.execute().unwrap();
+            assert_eq!(*seen.lock().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_overflow_block_throttles() {
+        let (mut graph, latest, seen) = build(Overflow::Block);
+        assert_eq!(graph.execute_until(latest).unwrap(), 1);
+        assert_eq!(graph.execute_until(latest).unwrap(), 2);
+        // The buffered consumer is two behind, so the counter is held back and the
+        // unbuffered consumer has nothing to run on.
+        assert!(matches!(
+            graph.execute_until(latest),
+            Err(ExecutionError::Throttled { .. })
+        ));
+        graph.execute_parallel().unwrap();
+        assert_eq!(*seen.lock().unwrap(), vec![1]);
+        graph.execute().unwrap();
+        graph.execute().unwrap();
+        assert_eq!(*seen.lock().unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_overflow_error() {
+        let (mut graph, latest, seen) = build(Overflow::Error);
+        graph.execute_until(latest).unwrap();
+        graph.execute_until(latest).unwrap();
+        for result in [graph.execute(), graph.execute_parallel()] {
+            assert!(matches!(result, Err(ExecutionError::ChannelFull { .. })));
+        }
+        assert!(seen.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,403 @@
+use super::*;
+use bincode::Options;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{path::Path, sync::PoisonError};
+
+/// Bumped whenever the layout of a checkpoint changes.
+const FORMAT_VERSION: u32 = 1;
+
+/// How [`Plan::checkpoint`] writes its resources.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckpointFormat {
+    /// Human readable JSON, for diffing and inspecting snapshots.
+    Json,
+    /// Compact bincode.
+    Binary,
+}
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Binary(bincode::Error),
+    /// The checkpoint was written in a layout this version of styx can't read.
+    UnsupportedVersion(u32),
+    /// `resource` was poisoned by a panic, so its data can't be trusted to save.
+    Poisoned {
+        resource: NodeIndex,
+        label: Option<String>,
+    },
+    /// The checkpoint doesn't fit the graph: one of them has a serializable resource at
+    /// `resource` that the other doesn't, or the two have different tags.
+    ShapeMismatch {
+        resource: NodeIndex,
+        expected: Option<String>,
+        found: Option<String>,
+    },
+}
+impl From<std::io::Error> for CheckpointError {
+    fn from(e: std::io::Error) -> Self {
+        CheckpointError::Io(e)
+    }
+}
+impl From<serde_json::Error> for CheckpointError {
+    fn from(e: serde_json::Error) -> Self {
+        CheckpointError::Json(e)
+    }
+}
+impl From<bincode::Error> for CheckpointError {
+    fn from(e: bincode::Error) -> Self {
+        CheckpointError::Binary(e)
+    }
+}
+
+/// Reads and writes the data of a serializable resource, whose type is only known when
+/// it's added.
+pub(crate) struct Codec {
+    tag: String,
+    to_json: fn(&AnyBox) -> serde_json::Result<serde_json::Value>,
+    from_json: fn(serde_json::Value) -> serde_json::Result<AnyBox>,
+    to_binary: fn(&AnyBox) -> bincode::Result<Vec<u8>>,
+    from_binary: fn(&[u8]) -> bincode::Result<AnyBox>,
+}
+impl Codec {
+    fn new<T: Serialize + DeserializeOwned + Send + Sync + 'static>(tag: String) -> Self {
+        Self {
+            tag,
+            to_json: |data| serde_json::to_value(downcast::<T>(data)),
+            from_json: |value| Ok(Box::new(serde_json::from_value::<T>(value)?)),
+            to_binary: |data| bincode::serialize(downcast::<T>(data)),
+            from_binary: |bytes| Ok(Box::new(from_bincode::<T>(bytes)?)),
+        }
+    }
+}
+/// Decodes like `bincode::deserialize`, but never reads past the end of `bytes`, so a
+/// corrupt or hostile length can't make it allocate more memory than the input's size.
+fn from_bincode<T: DeserializeOwned>(bytes: &[u8]) -> bincode::Result<T> {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(bytes.len() as u64)
+        .deserialize(bytes)
+}
+fn downcast<T: 'static>(data: &AnyBox) -> &T {
+    data.downcast_ref()
+        .expect("Resource registered with incorrect codec. CRITICAL LIBRARY BUG")
+}
+
+/// The on-disk layout. `D` is a JSON value or bincode bytes, so both formats share it.
+#[derive(Serialize, Deserialize)]
+struct Checkpoint<D> {
+    version: u32,
+    resources: Vec<Entry<D>>,
+}
+#[derive(Serialize, Deserialize)]
+struct Entry<D> {
+    index: u32,
+    tag: String,
+    data: D,
+}
+
+impl Plan {
+    /// Adds a resource that's saved by [`Plan::checkpoint`]. The tag names its type in
+    /// the checkpoint, so a checkpoint isn't restored into a graph that has a different
+    /// resource in its place.
+    pub fn add_serde_resource<T>(&mut self, tag: impl Into<String>, data: T) -> ResourceHandle<T>
+    where
+        T: Serialize + DeserializeOwned + Send + Sync + 'static,
+    {
+        let handle = self.add_resource(data);
+        self.codecs.insert(handle.idx, Codec::new::<T>(tag.into()));
+        handle
+    }
+
+    /// Writes every serializable resource, in order of their handles. Other resources,
+    /// queued outputs and the schedule aren't saved.
+    pub fn checkpoint(&self, format: CheckpointFormat) -> Result<Vec<u8>, CheckpointError> {
+        match format {
+            CheckpointFormat::Json => {
+                let checkpoint = self.encode(|codec, data| (codec.to_json)(data))?;
+                Ok(serde_json::to_vec_pretty(&checkpoint)?)
+            }
+            CheckpointFormat::Binary => {
+                let checkpoint = self.encode(|codec, data| (codec.to_binary)(data))?;
+                Ok(bincode::serialize(&checkpoint)?)
+            }
+        }
+    }
+
+    /// Puts the resources saved in `checkpoint` back. The graph has to have the same
+    /// serializable resources, with the same tags, as the one it was taken from -
+    /// typically the same graph, built again by the same code. Nothing is restored
+    /// unless all of it can be. Restored resources are no longer poisoned.
+    pub fn restore(
+        &mut self,
+        checkpoint: &[u8],
+        format: CheckpointFormat,
+    ) -> Result<(), CheckpointError> {
+        let restored = match format {
+            CheckpointFormat::Json => {
+                let checkpoint = serde_json::from_slice(checkpoint)?;
+                self.decode(checkpoint, |codec, data| Ok((codec.from_json)(data)?))?
+            }
+            CheckpointFormat::Binary => {
+                let checkpoint: Checkpoint<Vec<u8>> = from_bincode(checkpoint)?;
+                self.decode(checkpoint, |codec, data| Ok((codec.from_binary)(&data)?))?
+            }
+        };
+        for (node, data) in restored {
+            let Node::Resource(resource) = &self.graph[node] else {
+                unreachable!("Codecs are only registered for resources");
+            };
+            *resource.write().unwrap_or_else(PoisonError::into_inner) = data;
+            resource.clear_poison();
+            resource.bump();
+        }
+        Ok(())
+    }
+
+    pub fn save_checkpoint(
+        &self,
+        path: impl AsRef<Path>,
+        format: CheckpointFormat,
+    ) -> Result<(), CheckpointError> {
+        Ok(std::fs::write(path, self.checkpoint(format)?)?)
+    }
+    pub fn load_checkpoint(
+        &mut self,
+        path: impl AsRef<Path>,
+        format: CheckpointFormat,
+    ) -> Result<(), CheckpointError> {
+        self.restore(&std::fs::read(path)?, format)
+    }
+
+    fn encode<D, E>(
+        &self,
+        encode: impl Fn(&Codec, &AnyBox) -> Result<D, E>,
+    ) -> Result<Checkpoint<D>, CheckpointError>
+    where
+        CheckpointError: From<E>,
+    {
+        let mut nodes: Vec<_> = self.codecs.keys().copied().collect();
+        nodes.sort_unstable();
+        let mut resources = vec![];
+        for node in nodes {
+            let Node::Resource(resource) = &self.graph[node] else {
+                unreachable!("Codecs are only registered for resources");
+            };
+            let data = resource.read().map_err(|_| CheckpointError::Poisoned {
+                resource: node,
+                label: self.labels.get(&node).cloned(),
+            })?;
+            let codec = &self.codecs[&node];
+            resources.push(Entry {
+                index: node.index() as u32,
+                tag: codec.tag.clone(),
+                data: encode(codec, &data)?,
+            });
+        }
+        Ok(Checkpoint {
+            version: FORMAT_VERSION,
+            resources,
+        })
+    }
+
+    fn decode<D>(
+        &self,
+        checkpoint: Checkpoint<D>,
+        decode: impl Fn(&Codec, D) -> Result<AnyBox, CheckpointError>,
+    ) -> Result<Vec<(NodeIndex, AnyBox)>, CheckpointError> {
+        if checkpoint.version != FORMAT_VERSION {
+            return Err(CheckpointError::UnsupportedVersion(checkpoint.version));
+        }
+        let mut restored = vec![];
+        for entry in checkpoint.resources {
+            let node = NodeIndex::new(entry.index as usize);
+            let codec = self.codecs.get(&node);
+            if codec.is_none_or(|codec| codec.tag != entry.tag) {
+                return Err(CheckpointError::ShapeMismatch {
+                    resource: node,
+                    expected: codec.map(|codec| codec.tag.clone()),
+                    found: Some(entry.tag),
+                });
+            }
+            restored.push((node, decode(codec.unwrap(), entry.data)?));
+        }
+        let mut missing: Vec<_> = self
+            .codecs
+            .keys()
+            .filter(|node| !restored.iter().any(|(restored, _)| restored == *node))
+            .collect();
+        missing.sort_unstable();
+        if let Some(&node) = missing.first() {
+            return Err(CheckpointError::ShapeMismatch {
+                resource: *node,
+                expected: Some(self.codecs[node].tag.clone()),
+                found: None,
+            });
+        }
+        Ok(restored)
+    }
+}
+
+impl Schedule {
+    pub fn restore(
+        &mut self,
+        checkpoint: &[u8],
+        format: CheckpointFormat,
+    ) -> Result<(), CheckpointError> {
+        self.plan.restore(checkpoint, format)
+    }
+    pub fn load_checkpoint(
+        &mut self,
+        path: impl AsRef<Path>,
+        format: CheckpointFormat,
+    ) -> Result<(), CheckpointError> {
+        self.plan.load_checkpoint(path, format)
+    }
+}
+
+impl Executor {
+    // Like get_mut, these skip DerefMut, as restoring data doesn't change the graph.
+    pub fn restore(
+        &mut self,
+        checkpoint: &[u8],
+        format: CheckpointFormat,
+    ) -> Result<(), CheckpointError> {
+        self.plan.restore(checkpoint, format)
+    }
+    pub fn load_checkpoint(
+        &mut self,
+        path: impl AsRef<Path>,
+        format: CheckpointFormat,
+    ) -> Result<(), CheckpointError> {
+        self.plan.load_checkpoint(path, format)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Body {
+        position: [f32; 2],
+        velocity: [f32; 2],
+    }
+
+    /// A simulation whose bodies are saved, next to a scratch resource that isn't.
+    fn simulation() -> (Executor, ResourceHandle<Vec<Body>>, ResourceHandle<u64>) {
+        let mut graph = Executor::new();
+        let bodies = graph.add_serde_resource(
+            "bodies",
+            vec![Body {
+                position: [0.0, 0.0],
+                velocity: [1.0, 2.0],
+            }],
+        );
+        let steps = graph.add_serde_resource("steps", 0u64);
+        let scratch = graph.add_resource(String::new());
+        graph.add_task(
+            (Write(bodies), Write(steps), Write(scratch)),
+            |(mut bodies, mut steps, mut scratch)| {
+                for body in bodies.iter_mut() {
+                    body.position[0] += body.velocity[0];
+                    body.position[1] += body.velocity[1];
+                }
+                *steps += 1;
+                scratch.push('.');
+            },
+        );
+        (graph, bodies, steps)
+    }
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        for format in [CheckpointFormat::Json, CheckpointFormat::Binary] {
+            let (mut graph, bodies, steps) = simulation();
+            graph.execute().unwrap();
+            let checkpoint = graph.checkpoint(format).unwrap();
+            graph.execute().unwrap();
+            graph.execute_parallel().unwrap();
+            assert_eq!(*graph.get(steps).unwrap(), 3);
+
+            // Into the same graph, and into a fresh copy of it.
+            graph.restore(&checkpoint, format).unwrap();
+            assert_eq!(*graph.get(steps).unwrap(), 1);
+            let (mut copy, copy_bodies, _) = simulation();
+            copy.restore(&checkpoint, format).unwrap();
+            assert_eq!(graph.get(bodies).unwrap()[0].position, [1.0, 2.0]);
+            assert_eq!(*copy.get(copy_bodies).unwrap(), *graph.get(bodies).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_json_layout() {
+        let (graph, _, _) = simulation();
+        let json = String::from_utf8(graph.checkpoint(CheckpointFormat::Json).unwrap()).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["version"], 1);
+        assert_eq!(value["resources"][0]["tag"], "bodies");
+        assert_eq!(value["resources"][1]["data"], 0);
+        assert_eq!(value["resources"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_restore_checks_shape() {
+        let (graph, _, _) = simulation();
+        let checkpoint = graph.checkpoint(CheckpointFormat::Binary).unwrap();
+        let mut other = Executor::new();
+        other.add_serde_resource("bodies", Vec::<Body>::new());
+        other.add_serde_resource("frames", 0u64);
+        let Err(CheckpointError::ShapeMismatch {
+            resource,
+            expected,
+            found,
+        }) = other.restore(&checkpoint, CheckpointFormat::Binary)
+        else {
+            panic!("A checkpoint of another graph was restored");
+        };
+        assert_eq!(resource.index(), 1);
+        assert_eq!(expected.as_deref(), Some("frames"));
+        assert_eq!(found.as_deref(), Some("steps"));
+
+        let mut fewer = Executor::new();
+        fewer.add_serde_resource("bodies", Vec::<Body>::new());
+        assert!(fewer
+            .restore(&checkpoint, CheckpointFormat::Binary)
+            .is_err());
+        let mut more = simulation().0;
+        more.add_serde_resource("extra", 0u8);
+        assert!(more.restore(&checkpoint, CheckpointFormat::Binary).is_err());
+    }
+
+    #[test]
+    fn test_restore_rejects_huge_lengths() {
+        let (mut graph, _, _) = simulation();
+        // Format version 1, one resource at index 0, whose tag claims to be 2^62 bytes.
+        let mut checkpoint = vec![];
+        checkpoint.extend(1u32.to_le_bytes());
+        checkpoint.extend(1u64.to_le_bytes());
+        checkpoint.extend(0u32.to_le_bytes());
+        checkpoint.extend((1u64 << 62).to_le_bytes());
+        checkpoint.extend(b"bodies");
+        let result = graph.restore(&checkpoint, CheckpointFormat::Binary);
+        assert!(matches!(result, Err(CheckpointError::Binary(_))));
+    }
+
+    #[test]
+    fn test_checkpoint_file() {
+        let (mut graph, _, steps) = simulation();
+        let path = std::env::temp_dir().join(format!("styx-checkpoint-{}", std::process::id()));
+        graph.execute().unwrap();
+        graph
+            .save_checkpoint(&path, CheckpointFormat::Json)
+            .unwrap();
+        graph.execute().unwrap();
+        graph
+            .load_checkpoint(&path, CheckpointFormat::Json)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(*graph.get(steps).unwrap(), 1);
+    }
+}
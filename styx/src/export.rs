@@ -0,0 +1,277 @@
+use super::*;
+use std::{fmt::Write as _, time::Duration};
+
+/// How a graph is written out by [`Plan::to_dot`] and [`Plan::to_mermaid`].
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Dot,
+    Mermaid,
+}
+
+impl Plan {
+    /// Names a task or resource in exported graphs. Inside [`Plan::instantiate`], the
+    /// label is prefixed with the subgraph's name.
+    pub fn set_label(&mut self, node: impl Into<NodeHandle>, label: impl Into<String>) {
+        let node = node.into();
+        assert!(
+            self.contains(node),
+            "Only nodes still in the graph can be labelled"
+        );
+        self.insert_label(node.idx, label.into());
+    }
+
+    pub(crate) fn insert_label(&mut self, node: NodeIndex, label: String) {
+        let label = self.scoped_label(node, label);
+        self.labels.insert(node, label);
+    }
+
+    /// Adds a task like [`Plan::add_task`], labelled as [`Plan::set_label`] would.
+    pub fn add_labelled_task<F, I, O, D>(
+        &mut self,
+        label: impl Into<String>,
+        handles: I,
+        f: F,
+    ) -> TaskHandle<O>
+    where
+        F: for<'a> Fn(I::Data<'a>) -> O + Send + Sync + 'static,
+        O: Send + Sync + 'static,
+        I: Args<Receivers = D> + 'static,
+        D: ArgsState,
+    {
+        let task = self.add_task(handles, f);
+        self.set_label(task, label);
+        task
+    }
+
+    /// Adds a resource like [`Plan::add_resource`], labelled as [`Plan::set_label`] would.
+    pub fn add_labelled_resource<T>(
+        &mut self,
+        label: impl Into<String>,
+        data: T,
+    ) -> ResourceHandle<T>
+    where
+        T: Any + Send + Sync,
+    {
+        let resource = self.add_resource(data);
+        self.set_label(resource, label);
+        resource
+    }
+
+    /// The label of a task or resource, if it has one.
+    pub fn label(&self, node: impl Into<NodeIndex>) -> Option<&str> {
+        self.labels.get(&node.into()).map(String::as_str)
+    }
+
+    /// Renders the graph in Graphviz's DOT language. Tasks are boxes and resources are
+    /// cylinders; `Read` edges are dashed, `Write` edges bold, ordering-only edges dotted,
+    /// and feedback edges run backwards labelled with their iteration limit.
+    pub fn to_dot(&self) -> String {
+        self.render(Format::Dot, None)
+    }
+
+    /// Renders the graph as a Mermaid flowchart, styled like [`Plan::to_dot`]. Ordering-only
+    /// edges are long, grey and dotted, so they can't be mistaken for `Read`s.
+    pub fn to_mermaid(&self) -> String {
+        self.render(Format::Mermaid, None)
+    }
+
+    pub(crate) fn display_label(&self, node: NodeIndex) -> String {
+        match (self.labels.get(&node), self.graph.node_weight(node)) {
+            (Some(label), _) => label.clone(),
+            (None, Some(Node::Task(_))) => format!("task {}", node.index()),
+            (None, Some(Node::Resource(_))) => format!("resource {}", node.index()),
+            // Errors and traces can outlive the node they name.
+            (None, None) => format!("removed task {}", node.index()),
+        }
+    }
+
+    fn render(&self, format: Format, compiled: Option<&Compiled>) -> String {
+        let mut out = String::new();
+        match format {
+            Format::Dot => out.push_str("digraph styx {\n"),
+            Format::Mermaid => out.push_str("flowchart TD\n"),
+        }
+        for node in self.graph.node_indices() {
+            let mut label = self.display_label(node);
+            let timing = compiled
+                .and_then(|compiled| compiled.tasks.get(&node))
+                .and_then(CompiledTask::last_run);
+            if let Some(timing) = timing {
+                let separator = if format == Format::Dot { "\n" } else { "<br/>" };
+                label = format!("{label}{separator}{timing:.2?}");
+            }
+            let is_task = matches!(self.graph[node], Node::Task(_));
+            let id = node.index();
+            match (format, is_task) {
+                (Format::Dot, true) => {
+                    writeln!(out, "    n{id} [shape=box, label={:?}];", label)
+                }
+                (Format::Dot, false) => writeln!(
+                    out,
+                    "    n{id} [shape=cylinder, style=filled, fillcolor=lightgrey, label={:?}];",
+                    label
+                ),
+                (Format::Mermaid, true) => {
+                    writeln!(out, "    n{id}[\"{}\"]", mermaid_escape(&label))
+                }
+                (Format::Mermaid, false) => {
+                    writeln!(out, "    n{id}[(\"{}\")]", mermaid_escape(&label))
+                }
+            }
+            .unwrap();
+        }
+        // Mermaid styles links by their position, so the ordering-only ones are noted.
+        let mut order_links = vec![];
+        for (link, edge) in self.graph.edge_references().enumerate() {
+            let (from, to) = (edge.source().index(), edge.target().index());
+            let line = match (format, edge.weight().meta) {
+                (Format::Dot, Access::Consume) => format!("n{from} -> n{to};"),
+                (Format::Dot, Access::Share) => {
+                    format!("n{from} -> n{to} [arrowhead=odot, label=\"shared\"];")
+                }
+                (Format::Dot, Access::Read) => {
+                    format!("n{from} -> n{to} [style=dashed, color=blue, label=\"read\"];")
+                }
+                (Format::Dot, Access::Write) => {
+                    format!("n{from} -> n{to} [style=bold, color=red, label=\"write\"];")
+                }
+                (Format::Dot, Access::Order) => {
+                    format!("n{from} -> n{to} [style=dotted, label=\"after\"];")
+                }
+                (Format::Mermaid, Access::Consume) => format!("n{from} --> n{to}"),
+                (Format::Mermaid, Access::Share) => format!("n{from} --o|shared| n{to}"),
+                (Format::Mermaid, Access::Read) => format!("n{from} -.->|read| n{to}"),
+                (Format::Mermaid, Access::Write) => format!("n{from} ==>|write| n{to}"),
+                (Format::Mermaid, Access::Order) => {
+                    order_links.push(link.to_string());
+                    format!("n{from} --->|after| n{to}")
+                }
+            };
+            writeln!(out, "    {line}").unwrap();
+        }
+        for feedback in &self.feedback {
+            let (from, to) = (feedback.from.index(), feedback.to.index());
+            let limit = feedback.max_iterations;
+            let line = match format {
+                Format::Dot => format!(
+                    "n{from} -> n{to} [style=dashed, color=purple, constraint=false, label=\"feedback ×{limit}\"];"
+                ),
+                Format::Mermaid => format!("n{from} -.->|\"feedback ×{limit}\"| n{to}"),
+            };
+            writeln!(out, "    {line}").unwrap();
+        }
+        if format == Format::Mermaid && !order_links.is_empty() {
+            let links = order_links.join(",");
+            writeln!(
+                out,
+                "    linkStyle {links} stroke:grey,stroke-dasharray:2 4"
+            )
+            .unwrap();
+        }
+        if format == Format::Dot {
+            out.push_str("}\n");
+        }
+        out
+    }
+}
+
+/// Mermaid has no backslash escapes, quotes have to be written as an entity.
+fn mermaid_escape(label: &str) -> String {
+    label.replace('"', "#quot;")
+}
+
+impl CompiledTask {
+    /// How long the task's last poll took, if it's run since the graph was compiled.
+    pub(crate) fn last_run(&self) -> Option<Duration> {
+        match self.last_run.load(std::sync::atomic::Ordering::Relaxed) {
+            u64::MAX => None,
+            nanos => Some(Duration::from_nanos(nanos)),
+        }
+    }
+}
+
+impl Schedule {
+    /// Like [`Plan::to_dot`], with each task's time from the last run added to its label.
+    pub fn to_dot(&self) -> String {
+        self.plan.render(Format::Dot, Some(&self.compiled))
+    }
+
+    /// Like [`Plan::to_mermaid`], with each task's time from the last run added to its label.
+    pub fn to_mermaid(&self) -> String {
+        self.plan.render(Format::Mermaid, Some(&self.compiled))
+    }
+}
+
+impl Executor {
+    /// Like [`Plan::to_dot`], with timings from the last run if the graph hasn't changed since.
+    pub fn to_dot(&self) -> String {
+        self.plan.render(Format::Dot, self.compiled.as_ref())
+    }
+
+    /// Like [`Plan::to_mermaid`], with timings from the last run if the graph hasn't changed since.
+    pub fn to_mermaid(&self) -> String {
+        self.plan.render(Format::Mermaid, self.compiled.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dot_export() {
+        let mut plan = Plan::new();
+        let particles = plan.add_resource(0);
+        plan.set_label(particles, "particles");
+        let gravity = plan.add_task(Write(particles), |_| 1);
+        plan.set_label(gravity, "gravity");
+        let collide = plan.add_task((gravity, Read(particles)), |_| ());
+        plan.add_finite_feedback_edge(collide, gravity, 3);
+        assert_eq!(plan.label(gravity), Some("gravity"));
+        assert_eq!(plan.label(collide), None);
+        let dot = plan.to_dot();
+        assert!(dot.starts_with("digraph styx {\n"));
+        assert!(dot.contains(
+            "n0 [shape=cylinder, style=filled, fillcolor=lightgrey, label=\"particles\"];"
+        ));
+        assert!(dot.contains("n1 [shape=box, label=\"gravity\"];"));
+        assert!(dot.contains("n2 [shape=box, label=\"task 2\"];"));
+        assert!(dot.contains("n0 -> n1 [style=bold, color=red, label=\"write\"];"));
+        assert!(dot.contains("n1 -> n2;"));
+        assert!(dot.contains("n0 -> n2 [style=dashed, color=blue, label=\"read\"];"));
+        assert!(dot.contains(
+            "n2 -> n1 [style=dashed, color=purple, constraint=false, label=\"feedback ×3\"];"
+        ));
+    }
+
+    #[test]
+    fn test_mermaid_export_with_timings() {
+        let mut plan = Plan::new();
+        let value = plan.add_labelled_resource("a \"quoted\" value", 0);
+        let step = plan.add_task(Write(value), |_| {
+            std::thread::sleep(Duration::from_millis(2));
+        });
+        let done = plan.add_labelled_task("done", ().after(step), |_| ());
+        assert_eq!(plan.label(done), Some("done"));
+        let mut schedule = plan.build().unwrap();
+        assert!(!schedule.to_mermaid().contains("ms"));
+        schedule.execute().unwrap();
+        let mermaid = schedule.to_mermaid();
+        assert!(mermaid.starts_with("flowchart TD\n"));
+        assert!(mermaid.contains("n0[(\"a #quot;quoted#quot; value\")]"));
+        assert!(mermaid.contains("n1[\"task 1<br/>"));
+        assert!(mermaid.contains("n0 ==>|write| n1"));
+        assert!(mermaid.contains("n1 --->|after| n2"));
+        assert!(mermaid.contains("linkStyle 1 stroke:grey,stroke-dasharray:2 4"));
+        assert!(mermaid.contains("n2[\"done<br/>"));
+    }
+
+    #[test]
+    fn test_label_of_removed_task() {
+        let mut plan = Plan::new();
+        let task = plan.add_task((), |()| {});
+        assert_eq!(plan.display_label(task.idx), "task 0");
+        plan.remove_task(task);
+        assert_eq!(plan.display_label(task.idx), "removed task 0");
+    }
+}
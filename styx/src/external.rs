@@ -0,0 +1,312 @@
+use super::*;
+use std::sync::Condvar;
+
+/// A counter bumped whenever the outside end of an inlet or outlet changes, so a stream
+/// with nothing left to do can sleep until it might.
+#[derive(Default)]
+pub(crate) struct Wake {
+    generation: Mutex<u64>,
+    changed: Condvar,
+}
+impl Wake {
+    pub(crate) fn generation(&self) -> u64 {
+        *self.generation.lock().unwrap()
+    }
+    pub(crate) fn notify(&self) {
+        *self.generation.lock().unwrap() += 1;
+        self.changed.notify_all();
+    }
+    /// Blocks until the generation moves on from `since`.
+    pub(crate) fn wait(&self, since: u64) {
+        let generation = self.generation.lock().unwrap();
+        drop(
+            self.changed
+                .wait_while(generation, |g| *g == since)
+                .unwrap(),
+        );
+    }
+}
+
+/// The graph an inlet or outlet belonged to has been dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Disconnected;
+impl std::fmt::Display for Disconnected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the graph has been dropped")
+    }
+}
+impl std::error::Error for Disconnected {}
+
+/// Feeds values into a graph from outside, see [`Plan::add_inlet`]. The inlet's task
+/// fires once for each value sent. Once every clone of the inlet is dropped and the
+/// values already sent have been taken, its stream ends.
+pub struct Inlet<T> {
+    // Only None while dropping, so the channel is closed before the stream is woken.
+    sender: Option<Sender<T>>,
+    wake: Arc<Wake>,
+}
+impl<T> Inlet<T> {
+    /// Sends a value into the graph, blocking while the inlet's channel is full.
+    pub fn send(&self, value: T) -> Result<(), Disconnected> {
+        let sender = self.sender.as_ref().unwrap();
+        sender.send(value).map_err(|_| Disconnected)?;
+        self.wake.notify();
+        Ok(())
+    }
+    /// Like [`Inlet::send`], but waits for room without blocking the thread.
+    pub async fn send_async(&self, value: T) -> Result<(), Disconnected> {
+        let sender = self.sender.as_ref().unwrap().as_async();
+        sender.send(value).await.map_err(|_| Disconnected)?;
+        self.wake.notify();
+        Ok(())
+    }
+}
+impl<T> Clone for Inlet<T> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            wake: self.wake.clone(),
+        }
+    }
+}
+impl<T> Drop for Inlet<T> {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        self.wake.notify();
+    }
+}
+
+/// Hands values produced by a graph to the outside, see [`Plan::add_outlet`].
+pub struct Outlet<T> {
+    // Only None while dropping, so the channel is closed before the stream is woken.
+    receiver: Option<Receiver<Option<T>>>,
+    wake: Arc<Wake>,
+}
+impl<T> Outlet<T> {
+    /// Blocks until the graph produces a value. Returns `None` at the end of each
+    /// stream, and once the graph is dropped.
+    pub fn recv(&self) -> Option<T> {
+        let value = self.receiver.as_ref().unwrap().recv().ok().flatten();
+        self.wake.notify();
+        value
+    }
+    /// A value that's already waiting, if there is one.
+    pub fn try_recv(&self) -> Option<T> {
+        match self.receiver.as_ref().unwrap().try_recv() {
+            Ok(Some(value)) => {
+                self.wake.notify();
+                value
+            }
+            _ => None,
+        }
+    }
+    /// The values produced until the end of the current stream.
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(|| self.recv())
+    }
+}
+impl<T> Drop for Outlet<T> {
+    fn drop(&mut self) {
+        drop(self.receiver.take());
+        self.wake.notify();
+    }
+}
+
+pub(crate) struct InletFn<T> {
+    receiver: Receiver<T>,
+}
+impl<T: Send> TaskFn<(), T> for InletFn<T> {
+    fn call(&self, args: ()) -> Result<T, PollError> {
+        Ok(receive(&self.receiver)?)
+    }
+    fn is_source(&self) -> bool {
+        true
+    }
+    fn external(&self) -> bool {
+        true
+    }
+    fn kind(&self) -> TaskKind {
+        TaskKind::External
+    }
+    fn ready(&self) -> bool {
+        !self.receiver.is_empty()
+    }
+    fn closed(&self) -> bool {
+        self.receiver.is_terminated()
+    }
+}
+
+pub(crate) struct SinkFn<T> {
+    sender: Sender<Option<T>>,
+    capacity: usize,
+}
+impl<I, T> TaskFn<I, ()> for SinkFn<T>
+where
+    I: for<'a> Args<Data<'a> = T>,
+    T: Send,
+{
+    fn call(&self, args: T) -> Result<(), PollError> {
+        match self.sender.try_send(Some(args)) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(PollError::Closed),
+        }
+    }
+    fn external(&self) -> bool {
+        true
+    }
+    fn kind(&self) -> TaskKind {
+        TaskKind::External
+    }
+    fn ready(&self) -> bool {
+        self.sender.len() < self.capacity
+    }
+    fn closed(&self) -> bool {
+        self.sender.is_disconnected()
+    }
+    fn end_stream(&self) {
+        // There's always a slot spare for this, see add_outlet.
+        self.sender.try_send(None).ok();
+    }
+}
+
+impl Plan {
+    /// Adds a source task fed from outside the graph through the returned [`Inlet`].
+    /// Up to `capacity` values can be sent before the graph takes any. A stream waits on
+    /// the inlet until it's dropped.
+    pub fn add_inlet<T: Send + Sync + 'static>(
+        &mut self,
+        capacity: usize,
+    ) -> (Inlet<T>, TaskHandle<T>) {
+        let (sender, receiver) = kanal::bounded(capacity);
+        let handle = self.add_task_node((), InletFn { receiver });
+        let inlet = Inlet {
+            sender: Some(sender),
+            wake: self.wake.clone(),
+        };
+        (inlet, handle)
+    }
+
+    /// Adds a source task that produces the iterator's items, one per poll.
+    pub fn add_iter_source<It>(&mut self, iter: It) -> TaskHandle<It::Item>
+    where
+        It: IntoIterator,
+        It::IntoIter: Send + 'static,
+        It::Item: Send + Sync + 'static,
+    {
+        let iter = Mutex::new(iter.into_iter());
+        self.add_source((), move |()| iter.lock().unwrap().next())
+    }
+
+    /// Adds a source task that produces the items of an async stream. The stream is
+    /// driven on the executor's tokio runtime, and up to `capacity` items are read ahead.
+    pub fn add_stream_source<S>(&mut self, stream: S, capacity: usize) -> TaskHandle<S::Item>
+    where
+        S: futures_core::Stream + Send + 'static,
+        S::Item: Send + Sync + 'static,
+    {
+        let (inlet, handle) = self.add_inlet(capacity);
+        self.runtime().spawn(async move {
+            let mut stream = Box::pin(stream);
+            while let Some(item) = std::future::poll_fn(|cx| stream.as_mut().poll_next(cx)).await {
+                if inlet.send_async(item).await.is_err() {
+                    break;
+                }
+            }
+        });
+        handle
+    }
+
+    /// Adds a sink task that hands each of its inputs to the returned [`Outlet`]. It's
+    /// held back while `capacity` values are waiting there, so the outlet has to be read
+    /// from another thread, or hold everything a stream produces.
+    pub fn add_outlet<I, T, D>(
+        &mut self,
+        handles: I,
+        capacity: usize,
+    ) -> (TaskHandle<()>, Outlet<T>)
+    where
+        I: for<'a> Args<Data<'a> = T, Receivers = D> + 'static,
+        T: Send + Sync + 'static,
+        D: ArgsState,
+    {
+        // One more slot than asked for, so the end of a stream can always be marked.
+        let (sender, receiver) = kanal::bounded(capacity + 1);
+        let handle = self.add_task_node(handles, SinkFn { sender, capacity });
+        let outlet = Outlet {
+            receiver: Some(receiver),
+            wake: self.wake.clone(),
+        };
+        (handle, outlet)
+    }
+
+    /// Adds a sink task that calls `f` with each of its inputs. Unlike a task's body,
+    /// `f` can be `FnMut`, as it's never called from two threads at once.
+    pub fn add_callback_sink<F, I, D>(&mut self, handles: I, f: F) -> TaskHandle<()>
+    where
+        F: for<'a> FnMut(I::Data<'a>) + Send + 'static,
+        I: Args<Receivers = D> + 'static,
+        D: ArgsState,
+    {
+        let f = Mutex::new(f);
+        self.add_task(handles, move |args| (f.lock().unwrap())(args))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        pin::Pin,
+        task::{Context, Poll},
+        thread,
+    };
+
+    #[test]
+    fn test_inlet_to_outlet() {
+        let mut graph = Executor::new();
+        let (inlet, numbers) = graph.add_inlet(4);
+        let doubled = graph.add_task(numbers, |n: u32| n * 2);
+        let (_, outlet) = graph.add_outlet(doubled, 4);
+        let collector = thread::spawn(move || outlet.iter().collect::<Vec<_>>());
+        let feeder = thread::spawn(move || {
+            for n in 1..=100 {
+                inlet.send(n).unwrap();
+            }
+        });
+        graph.stream().unwrap();
+        feeder.join().unwrap();
+        let expected: Vec<_> = (1..=100).map(|n| n * 2).collect();
+        assert_eq!(collector.join().unwrap(), expected);
+    }
+
+    struct Countdown(u32);
+    impl futures_core::Stream for Countdown {
+        type Item = u32;
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<u32>> {
+            self.0 = self.0.saturating_sub(1);
+            Poll::Ready(Some(self.0).filter(|n| *n > 0))
+        }
+    }
+
+    #[test]
+    fn test_iter_and_stream_sources() {
+        let mut graph = Executor::new();
+        let letters = graph.add_iter_source(['a', 'b', 'c', 'd']);
+        let countdown = graph.add_stream_source(Countdown(4), 2);
+        let seen = Arc::new(Mutex::new(vec![]));
+        let store = seen.clone();
+        let mut calls = 0;
+        graph.add_callback_sink((letters, countdown), move |pair| {
+            calls += 1;
+            store.lock().unwrap().push((calls, pair));
+        });
+        let (_, outlet) = graph.add_outlet(countdown, 10);
+        graph.stream().unwrap();
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [(1, ('a', 3)), (2, ('b', 2)), (3, ('c', 1))]
+        );
+        assert_eq!(outlet.iter().collect::<Vec<_>>(), [3, 2, 1]);
+        assert_eq!(outlet.try_recv(), None);
+    }
+}
@@ -0,0 +1,251 @@
+use super::*;
+use std::{collections::HashSet, error::Error};
+
+pub(crate) type BoxError = Box<dyn Error + Send + Sync>;
+
+/// What happens to the rest of a run once a fallible task returns an error. Whatever the
+/// policy, the run's result is the first failure.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FailurePolicy {
+    /// Don't start any more tasks. Tasks already running under `execute_parallel` finish.
+    #[default]
+    Cancel,
+    /// Skip every task downstream of the failed one, including tasks ordered after it
+    /// with [`Args::after`], and run everything else.
+    SkipDependents,
+    /// Only skip tasks that need the failed task's output, directly or through other
+    /// skipped tasks. Tasks that are only ordered after it still run.
+    ContinueIndependent,
+}
+
+/// Why a task's poll didn't produce an output.
+#[derive(Debug)]
+pub(crate) enum PollError {
+    Receive(ReceiveError),
+    Failed(BoxError),
+    ChannelFull,
+    /// End of stream: a source ran dry, or a channel input was closed.
+    Closed,
+}
+impl From<ReceiveError> for PollError {
+    fn from(e: ReceiveError) -> Self {
+        match e {
+            ReceiveError::Closed => PollError::Closed,
+            e => PollError::Receive(e),
+        }
+    }
+}
+
+/// Failures so far in a run.
+#[derive(Default)]
+pub(crate) struct Failures {
+    /// Tasks that failed or were skipped.
+    pub(crate) failed: HashSet<NodeIndex>,
+    pub(crate) first: Option<ExecutionError>,
+    /// Tasks held back for a missing channel input.
+    pub(crate) held: HashSet<NodeIndex>,
+    /// Set under [`FailurePolicy::Cancel`] once anything has failed.
+    pub(crate) cancelled: bool,
+}
+impl Failures {
+    pub(crate) fn clear(&mut self) {
+        self.failed.clear();
+        self.first = None;
+        self.held.clear();
+        self.cancelled = false;
+    }
+}
+
+pub(crate) struct FallibleFn<F>(pub(crate) F);
+impl<F, I, O, E> TaskFn<I, O> for FallibleFn<F>
+where
+    F: for<'a> Fn(I::Data<'a>) -> Result<O, E> + Send + Sync,
+    I: Args,
+    E: Error + Send + Sync + 'static,
+{
+    fn call(&self, args: I::Data<'_>) -> Result<O, PollError> {
+        (self.0)(args).map_err(|e| PollError::Failed(e.into()))
+    }
+    fn kind(&self) -> TaskKind {
+        TaskKind::Fallible
+    }
+}
+
+impl Plan {
+    pub fn set_failure_policy(&mut self, policy: FailurePolicy) {
+        self.failure_policy = policy;
+    }
+
+    /// Adds a task that can fail. Dependents receive the `Ok` value; an `Err` ends up in
+    /// [`ExecutionError::TaskFailed`], and the failure policy decides what else runs.
+    pub fn add_fallible_task<F, I, O, E, D>(&mut self, handles: I, f: F) -> TaskHandle<O>
+    where
+        F: for<'a> Fn(I::Data<'a>) -> Result<O, E> + Send + Sync + 'static,
+        O: Send + Sync + 'static,
+        E: Error + Send + Sync + 'static,
+        I: Args<Receivers = D> + 'static,
+        D: ArgsState,
+    {
+        self.add_task_node(handles, FallibleFn(f))
+    }
+
+    /// Records that `node` failed with `error`.
+    pub(crate) fn fail(&self, failures: &mut Failures, node: NodeIndex, error: ExecutionError) {
+        failures.failed.insert(node);
+        failures.first.get_or_insert(error);
+        if self.failure_policy == FailurePolicy::Cancel {
+            failures.cancelled = true;
+        }
+    }
+
+    /// Whether `node` has to be skipped because a task it depends on failed or was skipped.
+    pub(crate) fn blocked(&self, failures: &Failures, node: NodeIndex) -> bool {
+        if failures.failed.is_empty() {
+            return false;
+        }
+        self.graph
+            .edges_directed(node, petgraph::Direction::Incoming)
+            .any(|edge| {
+                let needed = match self.failure_policy {
+                    FailurePolicy::ContinueIndependent => {
+                        matches!(edge.weight().meta, Access::Consume | Access::Share)
+                    }
+                    _ => true,
+                };
+                needed && failures.failed.contains(&edge.source())
+            })
+    }
+
+    /// Ends a run. If anything failed, outputs that never reached the tasks that were
+    /// skipped are dropped, so they don't leak into the next run. So are the inputs of
+    /// tasks that were held back, say by an arm that wasn't taken, or they'd be paired
+    /// with the next run's. Buffered channels keep theirs, as they would for any
+    /// consumer that falls behind.
+    pub(crate) fn finish_run(
+        &self,
+        compiled: &Compiled,
+        failures: &mut Failures,
+    ) -> Result<(), ExecutionError> {
+        for &consumer in &failures.held {
+            for edge in self
+                .graph
+                .edges_directed(consumer, petgraph::Direction::Incoming)
+            {
+                if let Node::Task(task) = &self.graph[edge.source()] {
+                    task.discard_outputs_to(consumer);
+                }
+            }
+        }
+        failures.held.clear();
+        let result = match failures.first.take() {
+            Some(error) => {
+                self.discard_outputs(&compiled.order, false);
+                Err(error)
+            }
+            None => Ok(()),
+        };
+        if let Some(recorder) = &self.recorder {
+            recorder.end_run();
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[derive(Debug)]
+    struct TooBig(i32);
+    impl std::fmt::Display for TooBig {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{} is too big", self.0)
+        }
+    }
+    impl Error for TooBig {}
+
+    /// `source -> check -> consumer`, plus a task ordered after `check` and an unrelated
+    /// one. They add 1, 10 and 100 to the counter when they run.
+    fn build(policy: FailurePolicy) -> (Executor, Arc<AtomicUsize>, ResourceHandle<i32>) {
+        let mut graph = Executor::new();
+        graph.set_failure_policy(policy);
+        let ran = Arc::new(AtomicUsize::new(0));
+        let value = graph.add_resource(1);
+        let source = graph.add_task(Read(value), |v| *v);
+        let check = graph.add_fallible_task(source, |v| match v {
+            v if v > 10 => Err(TooBig(v)),
+            v => Ok(v),
+        });
+        graph.set_label(check, "check");
+        let counter = ran.clone();
+        graph.add_task(check, move |_| counter.fetch_add(1, Ordering::SeqCst));
+        let counter = ran.clone();
+        graph.add_task(().after(check), move |_| {
+            counter.fetch_add(10, Ordering::SeqCst)
+        });
+        let counter = ran.clone();
+        graph.add_task((), move |_| counter.fetch_add(100, Ordering::SeqCst));
+        (graph, ran, value)
+    }
+
+    #[test]
+    fn test_fallible_task_succeeds() {
+        let (mut graph, ran, _) = build(FailurePolicy::Cancel);
+        graph.execute().unwrap();
+        graph.execute_parallel().unwrap();
+        assert_eq!(ran.load(Ordering::SeqCst), 222);
+    }
+
+    #[test]
+    fn test_failure_policies() {
+        let policies = [
+            (FailurePolicy::SkipDependents, 100),
+            (FailurePolicy::ContinueIndependent, 110),
+        ];
+        for (policy, expected) in policies {
+            let (mut graph, ran, value) = build(policy);
+            *graph.get_mut(value).unwrap() = 20;
+            for parallel in [false, true] {
+                ran.store(0, Ordering::SeqCst);
+                let result = if parallel {
+                    graph.execute_parallel()
+                } else {
+                    graph.execute()
+                };
+                let Err(ExecutionError::TaskFailed { label, error, .. }) = result else {
+                    panic!("Failure wasn't reported");
+                };
+                assert_eq!(label.as_deref(), Some("check"));
+                assert_eq!(error.to_string(), "20 is too big");
+                assert_eq!(ran.load(Ordering::SeqCst), expected);
+            }
+            // Nothing is left over from the failed run.
+            *graph.get_mut(value).unwrap() = 2;
+            ran.store(0, Ordering::SeqCst);
+            graph.execute().unwrap();
+            assert_eq!(ran.load(Ordering::SeqCst), 111);
+        }
+    }
+
+    #[test]
+    fn test_failure_cancels_run() {
+        let mut graph = Executor::new();
+        let ran = Arc::new(AtomicUsize::new(0));
+        let fail = graph.add_fallible_task((), |_| Err::<(), _>(TooBig(0)));
+        let counter = ran.clone();
+        graph.add_task(().after(fail), move |_| {
+            counter.fetch_add(1, Ordering::SeqCst)
+        });
+        assert!(matches!(
+            graph.execute(),
+            Err(ExecutionError::TaskFailed { label: None, .. })
+        ));
+        assert!(graph.execute_parallel().is_err());
+        assert!(graph.execute_until(fail).is_err());
+        assert_eq!(ran.load(Ordering::SeqCst), 0);
+    }
+}
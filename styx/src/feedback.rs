@@ -0,0 +1,345 @@
+use super::*;
+use petgraph::visit::{Dfs, Reversed};
+use std::collections::HashSet;
+
+/// A back-edge from `from` to `to`. Once `from` has run, every task on a path from `to`
+/// to `from` is run again, up to `max_iterations` passes in total.
+pub(crate) struct Feedback {
+    pub(crate) from: NodeIndex,
+    pub(crate) to: NodeIndex,
+    pub(crate) max_iterations: usize,
+    /// Checked after every pass, stops the loop early once it returns true.
+    pub(crate) until: Option<Box<dyn Fn() -> bool + Send + Sync>>,
+}
+
+/// A feedback edge resolved against the current graph.
+pub(crate) struct Loop {
+    pub(crate) feedback: usize,
+    /// The tasks that are re-run on each pass, in topological order.
+    pub(crate) region: Vec<NodeIndex>,
+    /// Tasks outside the loop that depend on a task inside it. They have to wait for
+    /// the whole loop, not just the first pass.
+    pub(crate) deferred: Vec<NodeIndex>,
+}
+
+impl Plan {
+    /// Re-runs the tasks between `to` and `from` until they've run `max_iterations`
+    /// times. Tasks in the loop can only take channel inputs from other tasks in the
+    /// loop - state that carries between passes belongs in a resource.
+    ///
+    /// Panics if `max_iterations` is zero - the loop always makes its first pass - or
+    /// if either task has been removed.
+    pub fn add_finite_feedback_edge<O, U>(
+        &mut self,
+        from: TaskHandle<O>,
+        to: TaskHandle<U>,
+        max_iterations: usize,
+    ) {
+        self.check_feedback(from, to, max_iterations);
+        self.feedback.push(Feedback {
+            from: from.idx,
+            to: to.idx,
+            max_iterations,
+            until: None,
+        });
+    }
+
+    /// Like [`Plan::add_finite_feedback_edge`], but stops early once `until` returns
+    /// true for `from`'s output.
+    pub fn add_feedback_edge_until<O, U, P>(
+        &mut self,
+        from: TaskHandle<O>,
+        to: TaskHandle<U>,
+        max_iterations: usize,
+        until: P,
+    ) where
+        O: Send + Sync + 'static,
+        P: Fn(&O) -> bool + Send + Sync + 'static,
+    {
+        self.check_feedback(from, to, max_iterations);
+        let Some(Node::Task(task)) = self.graph.node_weight_mut(from.idx) else {
+            unreachable!("The task was just checked");
+        };
+        // Only the latest output matters, and it mustn't hold `from` back.
+        let edge = Edge {
+            arg_idx: 0,
+            meta: Access::Share,
+            channel: Some(Channel {
+                capacity: 1,
+                overflow: Overflow::DropOldest,
+            }),
+            route: None,
+        };
+        // Read by the scheduler rather than a task, so there's no consumer node.
+        let receiver: Receiver<Arc<O>> = *task
+            .receiver(NodeIndex::end(), &edge)
+            .downcast()
+            .expect("Task scheduled with incorrect arguments. CRITICAL LIBRARY BUG");
+        let until = move || {
+            let mut last = None;
+            while let Ok(Some(v)) = receiver.try_recv() {
+                last = Some(v);
+            }
+            last.is_some_and(|v| until(&v))
+        };
+        self.feedback.push(Feedback {
+            from: from.idx,
+            to: to.idx,
+            max_iterations,
+            until: Some(Box::new(until)),
+        });
+    }
+
+    fn check_feedback<O, U>(&self, from: TaskHandle<O>, to: TaskHandle<U>, max_iterations: usize) {
+        assert!(
+            self.task_of(from).is_some() && self.task_of(to).is_some(),
+            "Feedback edges can only join tasks still in the graph"
+        );
+        assert!(max_iterations > 0, "A loop makes at least one pass");
+    }
+
+    /// Resolves every feedback edge into the set of tasks it repeats. Inner loops come
+    /// before the loops that contain them.
+    pub(crate) fn loops(&self) -> Result<Vec<Loop>, ExecutionError> {
+        let mut loops = vec![];
+        for (i, feedback) in self.feedback.iter().enumerate() {
+            let invalid = ExecutionError::InvalidFeedbackEdge {
+                from: feedback.from,
+                to: feedback.to,
+            };
+            let mut downstream = HashSet::new();
+            let mut dfs = Dfs::new(&self.graph, feedback.to);
+            while let Some(node) = dfs.next(&self.graph) {
+                downstream.insert(node);
+            }
+            let mut region = HashSet::new();
+            let reversed = Reversed(&self.graph);
+            let mut dfs = Dfs::new(reversed, feedback.from);
+            while let Some(node) = dfs.next(reversed) {
+                if downstream.contains(&node) {
+                    region.insert(node);
+                }
+            }
+            if !region.contains(&feedback.to) {
+                return Err(invalid);
+            }
+            let mut deferred = vec![];
+            for &node in &region {
+                for edge in self
+                    .graph
+                    .edges_directed(node, petgraph::Direction::Incoming)
+                {
+                    let outside_task = matches!(self.graph[edge.source()], Node::Task(_))
+                        && !region.contains(&edge.source());
+                    let channel = matches!(edge.weight().meta, Access::Consume | Access::Share);
+                    if outside_task && channel {
+                        return Err(invalid);
+                    }
+                }
+                for dependent in self
+                    .graph
+                    .neighbors_directed(node, petgraph::Direction::Outgoing)
+                {
+                    if !region.contains(&dependent) && !deferred.contains(&dependent) {
+                        deferred.push(dependent);
+                    }
+                }
+            }
+            loops.push((i, region, deferred));
+        }
+        for (i, (_, a, _)) in loops.iter().enumerate() {
+            for (_, b, _) in &loops[i + 1..] {
+                let nested = a.is_subset(b) != b.is_subset(a);
+                if !a.is_disjoint(b) && !nested {
+                    let feedback = &self.feedback[loops[i].0];
+                    return Err(ExecutionError::InvalidFeedbackEdge {
+                        from: feedback.from,
+                        to: feedback.to,
+                    });
+                }
+            }
+        }
+        loops.sort_by_key(|(_, region, _)| region.len());
+        let order = self.order(&[])?;
+        Ok(loops
+            .into_iter()
+            .map(|(feedback, region, deferred)| Loop {
+                feedback,
+                region: order
+                    .iter()
+                    .filter(|n| region.contains(n))
+                    .copied()
+                    .collect(),
+                deferred,
+            })
+            .collect())
+    }
+
+    /// The graph's dependencies between tasks, plus an edge from the end of each loop to
+    /// every task deferred until the loop has finished.
+    pub(crate) fn order_graph(&self, loops: &[Loop]) -> StableDiGraph<(), ()> {
+        let mut graph = self.graph.map(|_, _| (), |_, _| ());
+        for l in loops {
+            let from = self.feedback[l.feedback].from;
+            for &deferred in &l.deferred {
+                graph.add_edge(from, deferred, ());
+            }
+        }
+        graph
+    }
+
+    pub(crate) fn order(&self, loops: &[Loop]) -> Result<Vec<NodeIndex>, ExecutionError> {
+        petgraph::algo::toposort(&self.order_graph(loops), None)
+            .map_err(|_| ExecutionError::CyclicGraph)
+    }
+
+    /// Runs any further passes of the loops that end at `node`. `active` holds the loops
+    /// currently repeating further up the stack, which mustn't be restarted. A loop stops
+    /// early once any of its tasks fails or is skipped.
+    pub(crate) fn close_loops(
+        &self,
+        compiled: &Compiled,
+        node: NodeIndex,
+        active: &mut Vec<usize>,
+        failures: &mut Failures,
+    ) {
+        for (i, l) in compiled.loops.iter().enumerate() {
+            let feedback = &self.feedback[l.feedback];
+            if feedback.from != node || active.contains(&i) {
+                continue;
+            }
+            active.push(i);
+            for _ in 1..feedback.max_iterations {
+                if feedback.until.as_ref().is_some_and(|until| until())
+                    || l.region.iter().any(|node| failures.failed.contains(node))
+                {
+                    break;
+                }
+                self.discard_outputs(&l.region, true);
+                self.run_sequential(compiled, &l.region, active, failures);
+            }
+            active.pop();
+        }
+    }
+
+    /// Drops the outputs of the last pass that went to tasks outside the loop, so they
+    /// only see the final pass. Buffered channels are only emptied if `buffered` is set.
+    pub(crate) fn discard_outputs(&self, region: &[NodeIndex], buffered: bool) {
+        if let Some(recorder) = &self.recorder {
+            recorder.discard(region, buffered);
+        }
+        for &node in region {
+            if let Node::Task(task) = &self.graph[node] {
+                task.discard_outputs(buffered);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[test]
+    fn test_finite_feedback_edge() {
+        let mut graph = Executor::new();
+        let position = graph.add_resource(0i32);
+        let step = graph.add_task(Write(position), |mut p| *p += 1);
+        let bounce = graph.add_task(step, |_| ());
+        graph.add_finite_feedback_edge(bounce, step, 100);
+        graph.execute().unwrap();
+        assert_eq!(*graph.get(position).unwrap(), 100);
+        graph.execute_parallel().unwrap();
+        assert_eq!(*graph.get(position).unwrap(), 200);
+    }
+
+    #[test]
+    fn test_feedback_edge_until() {
+        let mut graph = Executor::new();
+        let position = graph.add_resource(1i32);
+        let step = graph.add_task(Write(position), |mut p| {
+            *p *= 2;
+            *p
+        });
+        graph.add_feedback_edge_until(step, step, 100, |p| *p >= 64);
+        graph.execute().unwrap();
+        assert_eq!(*graph.get(position).unwrap(), 64);
+        *graph.get_mut(position).unwrap() = 1;
+        graph.execute_parallel().unwrap();
+        assert_eq!(*graph.get(position).unwrap(), 64);
+    }
+
+    #[test]
+    #[should_panic(expected = "still in the graph")]
+    fn test_feedback_edge_rejects_removed_task() {
+        let mut graph = Executor::new();
+        let step = graph.add_task((), |()| 1);
+        let check = graph.add_task(step, |v| v);
+        graph.remove_task(step);
+        graph.add_feedback_edge_until(step, check, 3, |v| *v > 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one pass")]
+    fn test_feedback_edge_rejects_zero_passes() {
+        let mut graph = Executor::new();
+        let step = graph.add_task((), |()| 1);
+        graph.add_finite_feedback_edge(step, step, 0);
+    }
+
+    #[test]
+    fn test_feedback_downstream_sees_last_pass() {
+        let mut graph = Executor::new();
+        let counter = graph.add_resource(0usize);
+        let seen = Arc::new(AtomicUsize::new(0));
+        let step = graph.add_task(Write(counter), |mut c| {
+            *c += 1;
+            *c
+        });
+        let check = graph.add_task(step, |c| c);
+        let store = seen.clone();
+        graph.add_task(step, move |c| store.store(c, Ordering::SeqCst));
+        graph.add_finite_feedback_edge(check, step, 5);
+        graph.execute().unwrap();
+        assert_eq!(seen.load(Ordering::SeqCst), 5);
+        graph.execute_parallel().unwrap();
+        assert_eq!(seen.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    fn test_nested_feedback_edges() {
+        let mut graph = Executor::new();
+        let steps = graph.add_resource(0usize);
+        let settles = Arc::new(AtomicUsize::new(0));
+        let step = graph.add_task(Write(steps), |mut s| *s += 1);
+        let collide = graph.add_task(step, |_| ());
+        let counter = settles.clone();
+        let settle = graph.add_task(collide, move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        graph.add_finite_feedback_edge(collide, step, 3);
+        graph.add_finite_feedback_edge(settle, step, 4);
+        graph.execute().unwrap();
+        assert_eq!(*graph.get(steps).unwrap(), 12);
+        assert_eq!(settles.load(Ordering::SeqCst), 4);
+        graph.execute_parallel().unwrap();
+        assert_eq!(*graph.get(steps).unwrap(), 24);
+        assert_eq!(settles.load(Ordering::SeqCst), 8);
+    }
+
+    #[test]
+    fn test_invalid_feedback_edge() {
+        let mut graph = Executor::new();
+        let a = graph.add_task((), |_| 1);
+        let b = graph.add_task((), |_| 2);
+        graph.add_finite_feedback_edge(a, b, 2);
+        assert!(matches!(
+            graph.execute(),
+            Err(ExecutionError::InvalidFeedbackEdge { .. })
+        ));
+    }
+}
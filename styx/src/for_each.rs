@@ -0,0 +1,209 @@
+use super::*;
+use rayon::prelude::*;
+
+/// Arguments that can be split element-wise: `Read` or `Write` of a `Vec` resource, or
+/// a tuple of them to walk several `Vec`s of the same length side by side.
+pub trait Elements: Args {
+    /// A run of consecutive elements, handed to one worker.
+    type Chunk<'b>: Send;
+    type Item<'b>;
+    /// The number of elements, or an error if zipped `Vec`s differ in length.
+    fn len(data: &Self::Data<'_>) -> Result<usize, LengthMismatch>;
+    fn chunks<'b>(data: &'b mut Self::Data<'_>, size: usize) -> Vec<Self::Chunk<'b>>;
+    fn items<'b>(chunk: Self::Chunk<'b>) -> impl Iterator<Item = Self::Item<'b>>;
+}
+
+/// Zipped `Vec` resources that differ in length. A [`Plan::add_par_for_each`] over them
+/// fails with this rather than running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LengthMismatch {
+    /// The length of the first `Vec`.
+    pub expected: usize,
+    pub found: usize,
+}
+impl std::fmt::Display for LengthMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "zipped Vec resources differ in length: {} and {}",
+            self.expected, self.found
+        )
+    }
+}
+impl std::error::Error for LengthMismatch {}
+
+impl<T: Send + Sync + 'static> Elements for Read<ResourceHandle<Vec<T>>> {
+    type Chunk<'b> = &'b [T];
+    type Item<'b> = &'b T;
+    fn len(data: &Self::Data<'_>) -> Result<usize, LengthMismatch> {
+        Ok(data.len())
+    }
+    fn chunks<'b>(data: &'b mut Self::Data<'_>, size: usize) -> Vec<Self::Chunk<'b>> {
+        data.chunks(size).collect()
+    }
+    fn items<'b>(chunk: Self::Chunk<'b>) -> impl Iterator<Item = Self::Item<'b>> {
+        chunk.iter()
+    }
+}
+
+impl<T: Send + Sync + 'static> Elements for Write<ResourceHandle<Vec<T>>> {
+    type Chunk<'b> = &'b mut [T];
+    type Item<'b> = &'b mut T;
+    fn len(data: &Self::Data<'_>) -> Result<usize, LengthMismatch> {
+        Ok(data.len())
+    }
+    fn chunks<'b>(data: &'b mut Self::Data<'_>, size: usize) -> Vec<Self::Chunk<'b>> {
+        data.chunks_mut(size).collect()
+    }
+    fn items<'b>(chunk: Self::Chunk<'b>) -> impl Iterator<Item = Self::Item<'b>> {
+        chunk.iter_mut()
+    }
+}
+
+impl<I: Elements> Elements for After<I> {
+    type Chunk<'b> = I::Chunk<'b>;
+    type Item<'b> = I::Item<'b>;
+    fn len(data: &Self::Data<'_>) -> Result<usize, LengthMismatch> {
+        I::len(data)
+    }
+    fn chunks<'b>(data: &'b mut Self::Data<'_>, size: usize) -> Vec<Self::Chunk<'b>> {
+        I::chunks(data, size)
+    }
+    fn items<'b>(chunk: Self::Chunk<'b>) -> impl Iterator<Item = Self::Item<'b>> {
+        I::items(chunk)
+    }
+}
+
+macro_rules! elements_impl {
+    ($first:ident $(, $T:ident)*) => {
+        impl<$first: Elements $(, $T: Elements)*> Elements for ($first, $($T,)*) {
+            type Chunk<'b> = ($first::Chunk<'b>, $($T::Chunk<'b>,)*);
+            type Item<'b> = ($first::Item<'b>, $($T::Item<'b>,)*);
+            fn len(data: &Self::Data<'_>) -> Result<usize, LengthMismatch> {
+                #[allow(non_snake_case)]
+                let ($first, $($T,)*) = data;
+                let expected = $first::len($first)?;
+                $(
+                    let found = $T::len($T)?;
+                    if found != expected {
+                        return Err(LengthMismatch { expected, found });
+                    }
+                )*
+                Ok(expected)
+            }
+            fn chunks<'b>(data: &'b mut Self::Data<'_>, size: usize) -> Vec<Self::Chunk<'b>> {
+                #[allow(non_snake_case)]
+                let ($first, $($T,)*) = data;
+                #[allow(non_snake_case)]
+                let ($first, $($T,)*) = (
+                    $first::chunks($first, size).into_iter(),
+                    $($T::chunks($T, size).into_iter(),)*
+                );
+                $first
+                    $(.zip($T))*
+                    .map(
+                        #[allow(non_snake_case)]
+                        |elements_impl!(@nest $first $(, $T)*)| ($first, $($T,)*),
+                    )
+                    .collect()
+            }
+            fn items<'b>(chunk: Self::Chunk<'b>) -> impl Iterator<Item = Self::Item<'b>> {
+                #[allow(non_snake_case)]
+                let ($first, $($T,)*) = chunk;
+                $first::items($first)
+                    $(.zip($T::items($T)))*
+                    .map(
+                        #[allow(non_snake_case)]
+                        |elements_impl!(@nest $first $(, $T)*)| ($first, $($T,)*),
+                    )
+            }
+        }
+    };
+    // The left-nested tuple pattern that chained zips produce.
+    (@nest $first:ident) => { $first };
+    (@nest $first:ident, $second:ident $(, $T:ident)*) => {
+        elements_impl!(@nest_acc ($first, $second) $(, $T)*)
+    };
+    (@nest_acc $acc:tt) => { $acc };
+    (@nest_acc $acc:tt, $next:ident $(, $T:ident)*) => {
+        elements_impl!(@nest_acc ($acc, $next) $(, $T)*)
+    };
+}
+elements_impl!(T1);
+elements_impl!(T1, T2);
+elements_impl!(T1, T2, T3);
+elements_impl!(T1, T2, T3, T4);
+
+impl Plan {
+    /// Adds a task that calls `f` on every element of one or more `Vec` resources. The
+    /// elements are split into chunks of `chunk_size`, which run across the rayon thread
+    /// pool. As far as the rest of the graph is concerned it's one task, holding its
+    /// `Read` and `Write` leases for the whole pass. If zipped `Vec`s differ in length,
+    /// it fails with a [`LengthMismatch`] without touching any of them.
+    pub fn add_par_for_each<F, I, D>(
+        &mut self,
+        handles: I,
+        chunk_size: usize,
+        f: F,
+    ) -> TaskHandle<()>
+    where
+        F: for<'b> Fn(I::Item<'b>) + Send + Sync + 'static,
+        I: Elements + Args<Receivers = D> + 'static,
+        D: ArgsState,
+    {
+        assert!(chunk_size > 0, "Chunks have to hold at least one element");
+        self.add_fallible_task(handles, move |mut data| {
+            I::len(&data)?;
+            I::chunks(&mut data, chunk_size)
+                .into_par_iter()
+                .for_each(|chunk| I::items(chunk).for_each(&f));
+            Ok::<_, LengthMismatch>(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_par_for_each() {
+        let mut graph = Executor::new();
+        let positions = graph.add_resource(vec![0.0f32; 1000]);
+        let velocities = graph.add_resource((0..1000).map(|i| i as f32).collect::<Vec<_>>());
+        let gravity = graph.add_par_for_each(Write(velocities), 64, |v| *v -= 1.0);
+        graph.add_par_for_each(
+            (Write(positions), Read(velocities)).after(gravity),
+            100,
+            |(p, v)| *p += *v,
+        );
+        graph.execute().unwrap();
+        graph.execute_parallel().unwrap();
+        let positions = graph.get(positions).unwrap();
+        assert!(positions
+            .iter()
+            .enumerate()
+            .all(|(i, p)| *p == 2.0 * i as f32 - 3.0));
+    }
+
+    #[test]
+    fn test_par_for_each_length_mismatch() {
+        let mut graph = Executor::new();
+        let a = graph.add_resource(vec![1, 2, 3]);
+        let b = graph.add_resource(vec![1, 2]);
+        graph.add_par_for_each((Write(a), Read(b)), 1, |(a, b)| *a += b);
+        let Err(ExecutionError::TaskFailed { error, .. }) = graph.execute() else {
+            panic!("Mismatched lengths weren't caught");
+        };
+        let error = error.downcast::<LengthMismatch>().unwrap();
+        assert_eq!(
+            *error,
+            LengthMismatch {
+                expected: 3,
+                found: 2
+            }
+        );
+        assert!(!graph.is_poisoned(a));
+        assert_eq!(*graph.get(a).unwrap(), [1, 2, 3]);
+    }
+}
@@ -0,0 +1,312 @@
+use super::*;
+use std::time::{Duration, Instant};
+
+/// A resource that tasks can read but not write. The [`FrameLoop`] updates it between
+/// steps.
+#[derive(Debug)]
+pub struct Uniform<T>(ResourceHandle<T>);
+// Handles are Copy whatever T is, so these can't be derived.
+impl<T> Clone for Uniform<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<T> Copy for Uniform<T> {}
+impl<T> From<Uniform<T>> for NodeIndex {
+    fn from(uniform: Uniform<T>) -> Self {
+        uniform.0.idx
+    }
+}
+impl<T> From<Uniform<T>> for NodeHandle {
+    fn from(uniform: Uniform<T>) -> Self {
+        uniform.0.into()
+    }
+}
+impl<T: 'static> Args for Uniform<T> {
+    type Data<'a> = ReadGuard<'a, T>;
+    type Receivers = ();
+    fn get_edge_info(&self) -> Vec<(NodeHandle, Edge)> {
+        Read(self.0).get_edge_info()
+    }
+    fn prepare_inputs<'a>(
+        state: &Self::Receivers,
+        ctx: &mut RwGuards<'a>,
+    ) -> Result<Self::Data<'a>, ReceiveError> {
+        Read::<ResourceHandle<T>>::prepare_inputs(state, ctx)
+    }
+}
+
+/// The uniforms a [`FrameLoop`] keeps up to date.
+#[derive(Debug, Clone, Copy)]
+pub struct Clock {
+    /// How much time the current step covers.
+    pub dt: Uniform<Duration>,
+    /// How many steps have run before the current one.
+    pub frame: Uniform<u64>,
+}
+
+/// How much time each step of a [`FrameLoop`] covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timestep {
+    /// Steps of exactly this length, paced to real time. A frame runs however many
+    /// steps have come due since the last one.
+    Fixed(Duration),
+    /// One step per frame, covering the time since the last frame, but never more than
+    /// `max` - a long stall doesn't turn into one huge step.
+    Variable { max: Duration },
+}
+
+/// What a fixed timestep loop does once it has fallen more than a step behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lag {
+    /// Run the missed steps back to back, but no more than `max_steps` in a frame. Any
+    /// steps past that are skipped.
+    CatchUp { max_steps: u32 },
+    /// Run a single step and skip the rest.
+    Skip,
+}
+impl Default for Lag {
+    fn default() -> Self {
+        Lag::CatchUp { max_steps: 5 }
+    }
+}
+
+/// Timings for a single frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameStats {
+    /// The number of the frame's first step.
+    pub frame: u64,
+    /// Steps run in the frame.
+    pub steps: u32,
+    /// Steps that came due but were skipped under the [`Lag`] policy.
+    pub skipped: u32,
+    /// The time covered by each step.
+    pub dt: Duration,
+    /// Time spent executing the graph.
+    pub busy: Duration,
+    /// The slowest step.
+    pub slowest: Duration,
+}
+
+/// Runs a compiled graph over and over, once per step of simulated time, and keeps the
+/// [`Clock`] uniforms up to date for its tasks.
+pub struct FrameLoop {
+    schedule: Schedule,
+    clock: Clock,
+    timestep: Timestep,
+    lag: Lag,
+    parallel: bool,
+    /// Time that has come due but not been stepped through yet.
+    accumulated: Duration,
+    frame: u64,
+    last: Instant,
+}
+impl FrameLoop {
+    /// Panics if `timestep` is a fixed step of zero, which would never come due.
+    pub fn new(schedule: Schedule, clock: Clock, timestep: Timestep) -> Self {
+        assert!(
+            timestep != Timestep::Fixed(Duration::ZERO),
+            "A fixed timestep has to be longer than zero"
+        );
+        Self {
+            schedule,
+            clock,
+            timestep,
+            lag: Lag::default(),
+            parallel: false,
+            accumulated: Duration::ZERO,
+            frame: 0,
+            last: Instant::now(),
+        }
+    }
+    pub fn set_lag(&mut self, lag: Lag) {
+        self.lag = lag;
+    }
+    /// Run each step with `execute_parallel` rather than `execute`.
+    pub fn set_parallel(&mut self, parallel: bool) {
+        self.parallel = parallel;
+    }
+    pub fn into_schedule(self) -> Schedule {
+        self.schedule
+    }
+
+    /// Waits for the next step to come due under a fixed timestep, then runs a frame
+    /// covering the real time since the last one.
+    pub fn tick(&mut self) -> Result<FrameStats, ExecutionError> {
+        if let Timestep::Fixed(step) = self.timestep {
+            let due = step.saturating_sub(self.accumulated);
+            let since = self.last.elapsed();
+            if since < due {
+                std::thread::sleep(due - since);
+            }
+        }
+        let now = Instant::now();
+        let elapsed = now - self.last;
+        self.last = now;
+        self.advance(elapsed)
+    }
+
+    /// Runs a frame covering `elapsed`, without looking at the real clock - for driving
+    /// the loop from an outside event loop, or at a simulated pace.
+    pub fn advance(&mut self, elapsed: Duration) -> Result<FrameStats, ExecutionError> {
+        let (dt, due) = match self.timestep {
+            Timestep::Fixed(step) => {
+                self.accumulated += elapsed;
+                let due = self.accumulated.as_nanos() / step.as_nanos();
+                let due = match u32::try_from(due) {
+                    Ok(due) => {
+                        self.accumulated -= step * due;
+                        due
+                    }
+                    // Too far behind to count - whatever's past the cap is dropped.
+                    Err(_) => {
+                        let rest = self.accumulated.as_nanos() % step.as_nanos();
+                        self.accumulated = Duration::from_nanos(rest as u64);
+                        u32::MAX
+                    }
+                };
+                (step, due)
+            }
+            Timestep::Variable { max } => (elapsed.min(max), 1),
+        };
+        let steps = match self.lag {
+            Lag::CatchUp { max_steps } => due.min(max_steps),
+            Lag::Skip => due.min(1),
+        };
+        let mut stats = FrameStats {
+            frame: self.frame,
+            steps: 0,
+            skipped: due - steps,
+            dt,
+            busy: Duration::ZERO,
+            slowest: Duration::ZERO,
+        };
+        for _ in 0..steps {
+            self.schedule
+                .replace_resource(self.clock.dt.0, dt)
+                .expect("The clock belongs to another graph");
+            self.schedule
+                .replace_resource(self.clock.frame.0, self.frame)
+                .expect("The clock belongs to another graph");
+            let start = Instant::now();
+            if self.parallel {
+                self.schedule.execute_parallel()?;
+            } else {
+                self.schedule.execute()?;
+            }
+            let took = start.elapsed();
+            stats.busy += took;
+            stats.slowest = stats.slowest.max(took);
+            stats.steps += 1;
+            self.frame += 1;
+        }
+        Ok(stats)
+    }
+}
+impl Deref for FrameLoop {
+    type Target = Schedule;
+    fn deref(&self) -> &Schedule {
+        &self.schedule
+    }
+}
+impl DerefMut for FrameLoop {
+    fn deref_mut(&mut self) -> &mut Schedule {
+        &mut self.schedule
+    }
+}
+
+impl Plan {
+    /// Adds the uniforms a [`FrameLoop`] feeds its graph.
+    pub fn add_clock(&mut self) -> Clock {
+        Clock {
+            dt: Uniform(self.add_resource(Duration::ZERO)),
+            frame: Uniform(self.add_resource(0u64)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const STEP: Duration = Duration::from_millis(10);
+
+    /// A loop whose graph logs the clock at every step.
+    fn logging_loop(timestep: Timestep) -> (FrameLoop, ResourceHandle<Vec<(u64, Duration)>>) {
+        let mut plan = Plan::new();
+        let clock = plan.add_clock();
+        let log = plan.add_resource(vec![]);
+        plan.add_task(
+            (clock.frame, clock.dt, Write(log)),
+            |(frame, dt, mut log)| log.push((*frame, *dt)),
+        );
+        let frames = FrameLoop::new(plan.build().unwrap(), clock, timestep);
+        (frames, log)
+    }
+
+    #[test]
+    fn test_fixed_timestep_catches_up() {
+        let (mut frames, log) = logging_loop(Timestep::Fixed(STEP));
+        frames.set_lag(Lag::CatchUp { max_steps: 3 });
+        let stats = frames.advance(Duration::from_millis(25)).unwrap();
+        assert_eq!((stats.frame, stats.steps, stats.skipped), (0, 2, 0));
+        // 5ms were left over, so this is 10 steps due.
+        let stats = frames.advance(Duration::from_millis(100)).unwrap();
+        assert_eq!((stats.frame, stats.steps, stats.skipped), (2, 3, 7));
+        assert_eq!(frames.advance(Duration::from_millis(4)).unwrap().steps, 0);
+        assert_eq!(frames.advance(Duration::from_millis(1)).unwrap().steps, 1);
+        let log = frames.get(log).unwrap();
+        assert_eq!(
+            log.iter().map(|(frame, _)| *frame).collect::<Vec<_>>(),
+            [0, 1, 2, 3, 4, 5]
+        );
+        assert!(log.iter().all(|(_, dt)| *dt == STEP));
+    }
+
+    #[test]
+    fn test_fixed_timestep_skips() {
+        let (mut frames, log) = logging_loop(Timestep::Fixed(STEP));
+        frames.set_lag(Lag::Skip);
+        frames.set_parallel(true);
+        let stats = frames.advance(Duration::from_millis(35)).unwrap();
+        assert_eq!((stats.steps, stats.skipped), (1, 2));
+        assert!(stats.slowest <= stats.busy);
+        assert_eq!(frames.get(log).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_due_steps_are_capped() {
+        let (mut frames, _) = logging_loop(Timestep::Fixed(Duration::from_nanos(1)));
+        let stats = frames.advance(Duration::from_secs(10)).unwrap();
+        assert_eq!((stats.steps, stats.skipped), (5, u32::MAX - 5));
+        // The steps past the cap are gone, not left for the next frame.
+        let stats = frames.advance(Duration::ZERO).unwrap();
+        assert_eq!((stats.steps, stats.skipped), (0, 0));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_zero_fixed_timestep() {
+        logging_loop(Timestep::Fixed(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_variable_timestep() {
+        let (mut frames, log) = logging_loop(Timestep::Variable { max: 5 * STEP });
+        frames.advance(STEP / 2).unwrap();
+        frames.advance(Duration::from_secs(1)).unwrap();
+        frames.tick().unwrap();
+        let log = frames.get(log).unwrap();
+        assert_eq!(log[..2], [(0, STEP / 2), (1, 5 * STEP)]);
+        assert!(log[2].1 < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_tick_paces_fixed_timestep() {
+        let (mut frames, _) = logging_loop(Timestep::Fixed(Duration::from_millis(2)));
+        let start = Instant::now();
+        let steps: u32 = (0..3).map(|_| frames.tick().unwrap().steps).sum();
+        assert!(steps >= 3);
+        assert!(start.elapsed() >= Duration::from_millis(6));
+    }
+}
@@ -0,0 +1,151 @@
+use super::*;
+use petgraph::algo::{has_path_connecting, DfsSpace};
+
+/// What [`Plan::build`] does about two tasks that access the same resource, at least one
+/// of them writing, with no path between them in the graph.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HazardPolicy {
+    /// Fail with [`ExecutionError::ConflictingAccess`].
+    #[default]
+    Error,
+    /// Run the task that was added first before the other one.
+    InsertOrder,
+    /// Leave them unordered. They never overlap, since their leases conflict, but which
+    /// one runs first can change from run to run.
+    Allow,
+}
+
+impl Plan {
+    pub fn set_hazard_policy(&mut self, policy: HazardPolicy) {
+        self.hazard_policy = policy;
+    }
+
+    /// Finds every pair of tasks with conflicting, unordered accesses to a resource and
+    /// handles them according to the hazard policy. Added orderings go into `order_graph`.
+    pub(crate) fn resolve_hazards(
+        &self,
+        loops: &[Loop],
+        order_graph: &mut StableDiGraph<(), ()>,
+    ) -> Result<(), ExecutionError> {
+        if self.hazard_policy == HazardPolicy::Allow {
+            return Ok(());
+        }
+        let mut space = DfsSpace::new(&*order_graph);
+        for resource in self.graph.node_indices() {
+            if !matches!(self.graph[resource], Node::Resource(_)) {
+                continue;
+            }
+            let mut accesses: Vec<_> = self
+                .graph
+                .edges_directed(resource, petgraph::Direction::Outgoing)
+                .map(|edge| (edge.target(), edge.weight().meta))
+                .collect();
+            // Sorting by index makes the result deterministic.
+            accesses.sort_unstable_by_key(|(task, _)| *task);
+            for (i, &(first, first_access)) in accesses.iter().enumerate() {
+                for &(second, second_access) in &accesses[i + 1..] {
+                    let conflict = first_access == Access::Write || second_access == Access::Write;
+                    if !conflict
+                        || has_path_connecting(&*order_graph, first, second, Some(&mut space))
+                        || has_path_connecting(&*order_graph, second, first, Some(&mut space))
+                    {
+                        continue;
+                    }
+                    if self.hazard_policy == HazardPolicy::Error {
+                        return Err(ExecutionError::ConflictingAccess {
+                            first,
+                            second,
+                            resource,
+                            first_label: self.display_label(first),
+                            second_label: self.display_label(second),
+                            resource_label: self.display_label(resource),
+                        });
+                    }
+                    // If `first` sits in a loop that `second` is outside of, `second` has
+                    // to wait for the whole loop, not just one pass of it.
+                    let mut before = first;
+                    for l in loops {
+                        if l.region.contains(&first) && !l.region.contains(&second) {
+                            before = self.feedback[l.feedback].from;
+                        }
+                    }
+                    order_graph.add_edge(before, second, ());
+                    space = DfsSpace::new(&*order_graph);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_unordered_writes_rejected() {
+        let mut plan = Plan::new();
+        let counter = plan.add_labelled_resource("counter", 0);
+        let a = plan.add_labelled_task("increment", Write(counter), |mut c| *c += 1);
+        let b = plan.add_task(Read(counter), |c| *c);
+        let c = plan.add_task(Write(counter), |mut c| *c *= 2);
+        let Err(ExecutionError::ConflictingAccess {
+            first,
+            second,
+            resource,
+            first_label,
+            second_label,
+            resource_label,
+        }) = plan.build()
+        else {
+            panic!("Conflicting writes weren't caught");
+        };
+        assert_eq!((first, second, resource), (a.idx, b.idx, counter.idx));
+        assert_eq!(
+            (&*first_label, &*second_label, &*resource_label),
+            ("increment", "task 2", "counter")
+        );
+    }
+
+    #[test]
+    fn test_ordered_accesses_accepted() {
+        let mut plan = Plan::new();
+        let counter = plan.add_resource(0);
+        let a = plan.add_task(Write(counter), |mut c| *c += 1);
+        let b = plan.add_task(Read(counter).after(a), |c| *c);
+        // Reads never conflict with each other.
+        let c = plan.add_task(Read(counter).after(a), |c| *c);
+        plan.add_task((b, Write(counter)).after(c), |(_, mut c)| *c *= 2);
+        let mut schedule = plan.build().unwrap();
+        schedule.execute().unwrap();
+        assert_eq!(*schedule.get(counter).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_insert_order_policy() {
+        let mut graph = Executor::new();
+        graph.set_hazard_policy(HazardPolicy::InsertOrder);
+        let value = graph.add_resource(1);
+        graph.add_task(Write(value), |mut v| *v += 1);
+        graph.add_task(Write(value), |mut v| *v *= 10);
+        let read = graph.add_task(Read(value), |v| *v);
+        graph.execute_parallel().unwrap();
+        assert_eq!(*graph.output(read).unwrap(), 20);
+        *graph.get_mut(value).unwrap() = 1;
+        graph.execute().unwrap();
+        assert_eq!(*graph.output(read).unwrap(), 20);
+    }
+
+    #[test]
+    fn test_insert_order_waits_for_loop() {
+        let mut graph = Executor::new();
+        graph.set_hazard_policy(HazardPolicy::InsertOrder);
+        let value = graph.add_resource(0);
+        let step = graph.add_task(Write(value), |mut v| *v += 1);
+        let check = graph.add_task(step, |_| ());
+        graph.add_finite_feedback_edge(check, step, 4);
+        let read = graph.add_task(Read(value), |v| *v);
+        graph.execute_parallel().unwrap();
+        assert_eq!(*graph.output(read).unwrap(), 4);
+    }
+}
@@ -10,6 +10,11 @@ pub struct ExecutionGraph<'a> {
     _marker: PhantomData<fn() -> &'a ()>,
 }
 
+impl Default for ExecutionGraph<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl<'a> ExecutionGraph<'a> {
     pub fn new() -> Self {
         Self {
@@ -68,14 +73,13 @@ impl GraphExecutor for LinearExecutor {
         for node_index in nodes {
             match &graph.graph[node_index] {
                 inner::Node::InputData(data) => {
-                    let foo = result_store.insert(node_index, data.data.clone_box());
-                    assert!(foo.is_none());
+                    let previous = result_store.insert(node_index, data.data.clone_box());
+                    assert!(previous.is_none());
                 }
                 inner::Node::Stage(stage) => {
                     let mut inbound_edges = graph
                         .graph
                         .edges_directed(node_index, petgraph::Direction::Incoming)
-                        .into_iter()
                         .collect::<Vec<_>>();
                     inbound_edges.sort_unstable_by_key(|edge| edge.weight().arg_idx);
                     let mut rw_backing_store: Vec<(NodeIndex, ErasedData)> = vec![];
@@ -275,7 +279,7 @@ mod inner {
         where
             Self: Sized,
         {
-            if item.len() > 0 {
+            if !item.is_empty() {
                 return Err(":(");
             }
             Ok(())
@@ -287,13 +291,10 @@ mod inner {
             // unpack self into a tuple "T1, T2" etc.
             #[allow(non_snake_case)]
             let t = self;
-            let mut deps = Vec::new();
-            deps.push(t.dependency());
-
-            deps
+            vec![t.dependency()]
         }
         fn to_data<'b>(item: &'b mut [AnyRef<'b>]) -> Result<Self::Data<'b>, &'static str> {
-            Ok(T::to_data(&mut item[0])?)
+            T::to_data(&mut item[0])
         }
     }
 
@@ -351,8 +352,9 @@ mod inner {
     pub(crate) struct InputData {
         pub(crate) data: Box<dyn AnyClone>,
     }
+    pub(crate) type StageFn = dyn for<'b> Fn(&'b mut [AnyRef<'b>]) -> ErasedData;
     pub(crate) struct Stage {
-        pub(crate) op: Box<dyn for<'b> Fn(&'b mut [AnyRef<'b>]) -> ErasedData>,
+        pub(crate) op: Box<StageFn>,
     }
 }
 
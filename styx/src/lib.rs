@@ -1,99 +1,276 @@
 #![allow(unused)]
+mod branch;
+mod channel;
+mod checkpoint;
+mod export;
+mod external;
+mod failure;
+mod feedback;
+mod for_each;
+mod frame;
+mod hazard;
 pub mod legacy;
+mod memo;
+mod parallel;
+mod poison;
+mod replay;
+mod schedule;
+mod shared;
+mod soa;
+mod stream;
+mod subgraph;
+mod swap;
+mod trace;
 
+use branch::Route;
+pub use branch::{Arm, Either, Join, JoinReceivers, LeftArm, RightArm, SomeArm, Variant};
+use channel::Outgoing;
+pub use channel::{Buffered, Channel, Overflow};
+use checkpoint::Codec;
+pub use checkpoint::{CheckpointError, CheckpointFormat};
+pub use external::{Disconnected, Inlet, Outlet};
+use external::{InletFn, SinkFn, Wake};
+pub use failure::FailurePolicy;
+use failure::{BoxError, Failures, FallibleFn, PollError};
+use feedback::{Feedback, Loop};
+pub use for_each::{Elements, LengthMismatch};
+pub use frame::{Clock, FrameLoop, FrameStats, Lag, Timestep, Uniform};
+pub use hazard::HazardPolicy;
 use kanal::{Receiver, Sender};
+use memo::{PureFn, Resource};
 use petgraph::{
-    graph::{DiGraph, NodeIndex},
-    visit::{EdgeRef, IntoNeighborsDirected},
+    stable_graph::{NodeIndex, StableDiGraph},
+    visit::{EdgeRef, IntoEdgeReferences, IntoNeighborsDirected},
 };
+use poison::panic_message;
+pub use replay::{Event, Outcome, RecordedRun, Recording, Transition};
+use replay::{OutputCodec, Recorder};
+use schedule::{Compiled, CompiledTask, Polled};
+pub use schedule::{Executor, Schedule};
+pub use shared::Shared;
+use shared::{receive, Slot};
+pub use soa::{Row, Soa};
 use std::{
     any::{Any, TypeId},
+    collections::{HashMap, HashSet},
+    future::Future,
     marker::PhantomData,
     ops::{Deref, DerefMut, Index},
-    sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
 };
+use stream::SourceFn;
+use subgraph::Scope;
+pub use subgraph::Subgraph;
+use swap::TaskKind;
+use trace::Tracer;
+pub use trace::{Span, SpanKind, TaskSummary};
 
-pub struct Executor {
-    graph: DiGraph<Node, Edge>,
+/// A graph under construction. Once it's complete, [`Plan::build`] validates it and
+/// compiles it into a [`Schedule`] that can be executed any number of times.
+pub struct Plan {
+    graph: StableDiGraph<Node, Edge>,
+    feedback: Vec<Feedback>,
+    runtime: Option<tokio::runtime::Runtime>,
+    hazard_policy: HazardPolicy,
+    failure_policy: FailurePolicy,
+    labels: HashMap<NodeIndex, String>,
+    /// Wakes a stream that's waiting on an [`Inlet`] or [`Outlet`].
+    wake: Arc<Wake>,
+    tracer: Option<Tracer>,
+    /// The subgraphs being instantiated, outermost first.
+    scopes: Vec<Scope>,
+    /// Tasks that took input from a node that's been removed.
+    orphans: HashSet<NodeIndex>,
+    /// How to save the resources added with [`Plan::add_serde_resource`].
+    codecs: HashMap<NodeIndex, Codec>,
+    recorder: Option<Recorder>,
+    /// How to record the outputs of the tasks passed to [`Plan::record_output`].
+    output_codecs: HashMap<NodeIndex, OutputCodec>,
+    /// How many times a node at each index has been removed, so handles to a removed
+    /// node don't reach the node that takes its place.
+    generations: HashMap<NodeIndex, u32>,
 }
-impl Executor {
+impl Default for Plan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Drop for Plan {
+    fn drop(&mut self) {
+        // Dropping a runtime blocks until its tasks finish, which panics in async code.
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+impl Plan {
     pub fn new() -> Self {
         Self {
-            graph: DiGraph::new(),
+            graph: StableDiGraph::new(),
+            feedback: vec![],
+            runtime: None,
+            hazard_policy: HazardPolicy::default(),
+            failure_policy: FailurePolicy::default(),
+            labels: HashMap::new(),
+            wake: Arc::default(),
+            tracer: None,
+            scopes: vec![],
+            orphans: HashSet::new(),
+            codecs: HashMap::new(),
+            recorder: None,
+            output_codecs: HashMap::new(),
+            generations: HashMap::new(),
         }
     }
 
     pub fn add_task<F, I, O, D>(&mut self, handles: I, f: F) -> TaskHandle<O>
     where
-        F: for<'a> Fn(I::Data<'a>) -> O + 'static,
-        O: Clone + 'static,
+        F: for<'a> Fn(I::Data<'a>) -> O + Send + Sync + 'static,
+        O: Send + Sync + 'static,
+        I: Args<Receivers = D> + 'static,
+        D: ArgsState,
+    {
+        self.add_task_node(handles, SyncFn(f))
+    }
+
+    /// Adds a task whose body is a future, driven to completion on the executor's tokio
+    /// runtime. Leases on `Read`/`Write` arguments are held until the future resolves, so
+    /// they stay valid across `.await` points. The closure has to box its future:
+    /// `|Inputs(x)| Box::pin(async move { ... })`. Under `execute_parallel`, async tasks
+    /// that don't conflict wait on their IO at the same time.
+    pub fn add_async_task<F, I, O, D>(&mut self, handles: I, f: F) -> TaskHandle<O>
+    where
+        F: for<'a> Fn(Inputs<'a, I>) -> BoxFuture<'a, O> + Send + Sync + 'static,
+        O: Send + Sync + 'static,
+        I: Args<Receivers = D> + 'static,
+        D: ArgsState,
+    {
+        let runtime = self.runtime();
+        self.add_task_node(handles, AsyncFn { f, runtime })
+    }
+
+    /// The executor's tokio runtime, started the first time it's needed.
+    fn runtime(&mut self) -> tokio::runtime::Handle {
+        self.runtime
+            .get_or_insert_with(|| {
+                tokio::runtime::Builder::new_multi_thread()
+                    .enable_all()
+                    .build()
+                    .expect("Failed to start the tokio runtime")
+            })
+            .handle()
+            .clone()
+    }
+
+    fn add_task_node<T, I, O, D>(&mut self, handles: I, f: T) -> TaskHandle<O>
+    where
+        T: TaskFn<I, O> + 'static,
+        O: Send + Sync + 'static,
+        I: Args<Receivers = D> + 'static,
+        D: ArgsState,
+    {
+        // Producers learn who they're sending to before the task exists, so its index is
+        // held by a stand-in until then.
+        self.check_args(&handles);
+        let node_index = self.add_node(Node::resource(()));
+        self.connect_task(node_index, handles, f, vec![]);
+        TaskHandle::new(node_index, self.generation(node_index))
+    }
+
+    /// Panics if `handles` name a node that's been removed, or a resource they write
+    /// more than once. Checked before a task is added or replaced, so the graph is left
+    /// as it was.
+    pub(crate) fn check_args(&self, handles: &impl Args) {
+        let edges = handles.get_edge_info();
+        assert!(
+            edges.iter().all(|(handle, _)| self.contains(*handle)),
+            "A task can only take nodes still in the graph"
+        );
+        for (i, (handle, edge)) in edges.iter().enumerate() {
+            let aliased = edges[i + 1..].iter().any(|(other, other_edge)| {
+                let write = edge.meta == Access::Write || other_edge.meta == Access::Write;
+                other == handle && write
+            });
+            assert!(
+                !aliased,
+                "A task can't take a resource it writes more than once"
+            );
+        }
+    }
+
+    /// Puts the task `f` at `node`, wired to `handles`, sending its output down
+    /// `outgoing` and any channels opened to it later.
+    pub(crate) fn connect_task<T, I, O, D>(
+        &mut self,
+        node: NodeIndex,
+        handles: I,
+        f: T,
+        outgoing: Vec<Outgoing<Arc<O>>>,
+    ) where
+        T: TaskFn<I, O> + 'static,
+        O: Send + Sync + 'static,
         I: Args<Receivers = D> + 'static,
         D: ArgsState,
     {
+        let edges = handles.get_edge_info();
         let mut receivers = vec![];
-        for (handle, _) in handles.get_edge_info() {
-            if let Node::Task(ref mut t) = self.graph[handle] {
-                receivers.push(t.receiver())
+        for (handle, edge) in &edges {
+            let Node::Task(ref mut t) = &mut self.graph[handle.idx] else {
+                continue;
+            };
+            if let Access::Consume | Access::Share = edge.meta {
+                receivers.push(t.receiver(node, edge));
             }
         }
-        let node_index = self
-            .graph
-            .add_node(Node::task::<F, I, O>(f, D::downcast(receivers)));
-        for (handle, connection) in handles.get_edge_info() {
-            self.graph.add_edge(handle, node_index, connection);
+        let receivers = D::downcast(&mut receivers.into_iter());
+        let mut task = TaskData::<T, I, O>::new(f, receivers);
+        task.outgoing = outgoing;
+        self.graph[node] = Node::Task(Box::new(task));
+        for (handle, connection) in edges {
+            self.graph.add_edge(handle.idx, node, connection);
         }
-        TaskHandle {
-            idx: node_index,
-            _marker: PhantomData,
+    }
+
+    pub(crate) fn add_node(&mut self, node: Node) -> NodeIndex {
+        let index = self.graph.add_node(node);
+        for scope in &mut self.scopes {
+            scope.nodes.insert(index);
         }
+        index
     }
 
     pub fn add_resource<T>(&mut self, data: T) -> ResourceHandle<T>
     where
-        T: Any,
+        T: Any + Send + Sync,
     {
-        let node_index = self.graph.add_node(Node::resource(data));
-        ResourceHandle {
-            idx: node_index,
-            _marker: PhantomData,
-        }
+        let node_index = self.add_node(Node::resource(data));
+        ResourceHandle::new(node_index, self.generation(node_index))
     }
-    #[must_use]
-    pub fn execute(&mut self) -> Result<(), ExecutionError> {
-        let Ok(nodes) = petgraph::algo::toposort(&self.graph, None) else {
-            return Err(ExecutionError::CyclicGraph);
+    /// Orders `after` to run once `before` has finished, without passing any data - like
+    /// [`Args::after`], for tasks that have already been added.
+    pub fn add_order<A, B>(&mut self, before: TaskHandle<A>, after: TaskHandle<B>) {
+        assert!(
+            self.task_of(before).is_some() && self.task_of(after).is_some(),
+            "Only tasks still in the graph can be ordered"
+        );
+        let edge = Edge {
+            arg_idx: usize::MAX,
+            meta: Access::Order,
+            channel: None,
+            route: None,
         };
-        for node in nodes {
-            let Node::Task(task) = &self.graph[node] else {
-                continue;
-            };
-            let rw_guards = self.acquire_guards(node);
-            match task.poll(rw_guards) {
-                Ok(()) => Ok(()),
-                Err(e) => unreachable!("Scheduled incorrectly! {:?}", e),
-            }?
-        }
-        Ok(())
+        self.graph.add_edge(before.idx, after.idx, edge);
     }
-    // I un-elided the lifetimes here for my own understanding.
-    fn acquire_guards<'a>(&'a self, node: NodeIndex) -> RwGuards<'a> {
-        let mut refs = vec![];
-        for edge in self
-            .graph
-            .edges_directed(node, petgraph::Direction::Incoming)
-        {
-            if let Node::Resource(rw_lock) = &self.graph[edge.source()] {
-                refs.push((rw_lock, edge.weight()));
-            }
-        }
-        refs.sort_unstable_by_key(|(_, k)| k.arg_idx);
-        let refs = refs.into_iter().map(|(k, _)| k).collect();
-        RwGuards { refs }
+    pub fn build(self) -> Result<Schedule, ExecutionError> {
+        let compiled = Compiled::new(&self)?;
+        Ok(Schedule::new(self, compiled))
     }
     pub fn get<'a, T>(&'a self, resource_handle: ResourceHandle<T>) -> Option<ReadGuard<'a, T>> {
-        let Node::Resource(ref resource) = &self.graph[resource_handle.idx] else {
-            return None;
-        };
+        let resource = self.resource_of(resource_handle)?;
         Some(ReadGuard {
             guard: resource.read().ok()?,
             _marker: PhantomData,
@@ -103,15 +280,36 @@ impl Executor {
         &'a mut self,
         resource_handle: ResourceHandle<T>,
     ) -> Option<WriteGuard<'a, T>> {
-        let Node::Resource(ref mut resource) = &mut self.graph[resource_handle.idx] else {
-            return None;
-        };
+        let resource = self.resource_of(resource_handle)?;
+        let guard = resource.write().ok()?;
+        resource.bump();
         Some(WriteGuard {
-            guard: resource.write().ok()?,
+            guard,
             _marker: PhantomData,
         })
     }
+    /// The output of the last run of a task, unless it's been taken, or moved into a
+    /// consumer that takes it by value.
+    pub fn output<'a, T: 'static>(
+        &'a self,
+        task_handle: TaskHandle<T>,
+    ) -> Option<OutputGuard<'a, T>> {
+        let task = self.task_of(task_handle)?;
+        let slot: &Mutex<Slot<T>> = task.output().downcast_ref()?;
+        Some(OutputGuard {
+            output: slot.lock().ok()?.get()?,
+            _marker: PhantomData,
+        })
+    }
+    /// Takes the output of the last run of a task. Outputs still shared with a consumer
+    /// can't be taken.
+    pub fn take_output<T: 'static>(&mut self, task_handle: TaskHandle<T>) -> Option<T> {
+        let task = self.task_of(task_handle)?;
+        let slot: &Mutex<Slot<T>> = task.output().downcast_ref()?;
+        slot.lock().ok()?.take()
+    }
 }
+pub use inner::Args;
 use inner::*;
 mod inner {
     use std::sync::RwLockReadGuard;
@@ -123,133 +321,281 @@ mod inner {
     /// dependencies tell use where to source the arguments and
     pub trait Args {
         type Data<'a>;
-        type Receivers;
-        fn get_edge_info(&self) -> Vec<(NodeIndex, Edge)>;
+        type Receivers: ArgsState + Send + Sync;
+        fn get_edge_info(&self) -> Vec<(NodeHandle, Edge)>;
         fn prepare_inputs<'a>(
             receivers: &Self::Receivers,
-            rw_guards: &'a mut RwGuards,
+            rw_guards: &mut RwGuards<'a>,
         ) -> Result<Self::Data<'a>, ReceiveError>;
+
+        /// Orders the task after `task` finishes, without taking its output.
+        fn after<T>(self, task: TaskHandle<T>) -> After<Self>
+        where
+            Self: Sized,
+        {
+            After {
+                args: self,
+                after: vec![task.into()],
+            }
+        }
     }
 
     impl Args for () {
         type Data<'a> = ();
         type Receivers = ();
-        fn get_edge_info(&self) -> Vec<(NodeIndex, Edge)> {
+        fn get_edge_info(&self) -> Vec<(NodeHandle, Edge)> {
             vec![]
         }
         fn prepare_inputs<'a>(
             receivers: &Self::Receivers,
-            rw_guards: &'a mut RwGuards,
+            rw_guards: &mut RwGuards<'a>,
         ) -> Result<Self::Data<'a>, ReceiveError> {
             Ok(())
         }
     }
 
-    impl<T> Args for TaskHandle<T> {
+    /// Takes the output by value. The last consumer to receive it moves it; any others
+    /// get a clone. Use [`Shared`] for outputs that can't or shouldn't be cloned.
+    impl<T: Clone + Send + Sync + 'static> Args for TaskHandle<T> {
         type Data<'a> = T;
-        type Receivers = Receiver<T>;
-        fn get_edge_info(&self) -> Vec<(NodeIndex, Edge)> {
+        type Receivers = Receiver<Arc<T>>;
+        fn get_edge_info(&self) -> Vec<(NodeHandle, Edge)> {
             vec![(
-                self.idx,
+                (*self).into(),
                 Edge {
                     arg_idx: 0,
                     meta: Access::Consume,
+                    channel: None,
+                    route: None,
                 },
             )]
         }
         fn prepare_inputs<'a>(
             state: &Self::Receivers,
-            ctx: &'a mut RwGuards,
+            ctx: &mut RwGuards<'a>,
         ) -> Result<Self::Data<'a>, ReceiveError> {
-            match state.try_recv() {
-                Ok(Some(v)) => Ok(v),
-                Ok(None) => Err(ReceiveError::Empty),
-                Err(kanal::ReceiveError::Closed) => Err(ReceiveError::Closed),
-                Err(kanal::ReceiveError::SendClosed) => Err(ReceiveError::Closed),
-            }
+            let output = receive(state)?;
+            Ok(Arc::try_unwrap(output).unwrap_or_else(|output| T::clone(&output)))
         }
     }
     impl<T: 'static> Args for Read<ResourceHandle<T>> {
         type Data<'a> = ReadGuard<'a, T>;
         type Receivers = ();
-        fn get_edge_info(&self) -> Vec<(NodeIndex, Edge)> {
+        fn get_edge_info(&self) -> Vec<(NodeHandle, Edge)> {
             vec![(
-                self.0.idx,
+                self.0.into(),
                 Edge {
                     arg_idx: 0,
                     meta: Access::Read,
+                    channel: None,
+                    route: None,
                 },
             )]
         }
         fn prepare_inputs<'a>(
             state: &Self::Receivers,
-            ctx: &'a mut RwGuards,
+            ctx: &mut RwGuards<'a>,
         ) -> Result<Self::Data<'a>, ReceiveError> {
-            match ctx.refs[0].try_read() {
+            match ctx.next().try_read() {
                 Ok(guard) => Ok(ReadGuard {
                     guard,
                     _marker: PhantomData,
                 }),
                 Err(std::sync::TryLockError::WouldBlock) => Err(ReceiveError::WouldBlock),
-                Err(_) => panic!("Resource poisoned"),
+                Err(std::sync::TryLockError::Poisoned(_)) => Err(ReceiveError::Poisoned),
             }
         }
     }
     impl<T: 'static> Args for Write<ResourceHandle<T>> {
         type Data<'a> = WriteGuard<'a, T>;
         type Receivers = ();
-        fn get_edge_info(&self) -> Vec<(NodeIndex, Edge)> {
+        fn get_edge_info(&self) -> Vec<(NodeHandle, Edge)> {
             vec![(
-                self.0.idx,
+                self.0.into(),
                 Edge {
                     arg_idx: 0,
                     meta: Access::Write,
+                    channel: None,
+                    route: None,
                 },
             )]
         }
         fn prepare_inputs<'a>(
             state: &Self::Receivers,
-            ctx: &'a mut RwGuards,
+            ctx: &mut RwGuards<'a>,
         ) -> Result<Self::Data<'a>, ReceiveError> {
-            match ctx.refs[0].try_write() {
-                Ok(guard) => Ok(WriteGuard {
-                    guard,
-                    _marker: PhantomData,
-                }),
+            let resource = ctx.next();
+            match resource.try_write() {
+                Ok(guard) => {
+                    resource.bump();
+                    Ok(WriteGuard {
+                        guard,
+                        _marker: PhantomData,
+                    })
+                }
                 Err(std::sync::TryLockError::WouldBlock) => Err(ReceiveError::WouldBlock),
-                Err(_) => panic!("Resource poisoned"),
+                Err(std::sync::TryLockError::Poisoned(_)) => Err(ReceiveError::Poisoned),
             }
         }
     }
 
-    // maybe should just be From<>, idk.
+    macro_rules! args_impl {
+        ( $($T:ident),+) => {
+            impl<$($T: Args),+> Args for ($($T,)+) {
+                type Data<'a> = ($($T::Data<'a>,)+);
+                type Receivers = ($($T::Receivers,)+);
+                fn get_edge_info(&self) -> Vec<(NodeHandle, Edge)> {
+                    #[allow(non_snake_case)]
+                    let ($($T,)+) = self;
+                    let mut edges = Vec::new();
+                    $(edges.extend($T.get_edge_info());)+
+                    // Resource leases are handed out in arg_idx order, so number the
+                    // edges in the order the elements will ask for them.
+                    for (arg_idx, (_, edge)) in edges.iter_mut().enumerate() {
+                        edge.arg_idx = arg_idx;
+                    }
+                    edges
+                }
+                fn prepare_inputs<'a>(
+                    receivers: &Self::Receivers,
+                    rw_guards: &mut RwGuards<'a>,
+                ) -> Result<Self::Data<'a>, ReceiveError> {
+                    #[allow(non_snake_case)]
+                    let ($($T,)+) = receivers;
+                    Ok(($($T::prepare_inputs($T, rw_guards)?,)+))
+                }
+            }
+            impl<$($T: ArgsState),+> ArgsState for ($($T,)+) {
+                fn downcast(receivers: &mut dyn Iterator<Item = Box<dyn Any>>) -> Self {
+                    ($($T::downcast(receivers),)+)
+                }
+                fn ready(&self) -> bool {
+                    #[allow(non_snake_case)]
+                    let ($($T,)+) = self;
+                    true $(&& $T.ready())+
+                }
+                fn closed(&self) -> bool {
+                    #[allow(non_snake_case)]
+                    let ($($T,)+) = self;
+                    false $(|| $T.closed())+
+                }
+                fn skip(&self) {
+                    #[allow(non_snake_case)]
+                    let ($($T,)+) = self;
+                    $($T.skip();)+
+                }
+            }
+        };
+    }
+    args_impl!(T1);
+    args_impl!(T1, T2);
+    args_impl!(T1, T2, T3);
+    args_impl!(T1, T2, T3, T4);
+    args_impl!(T1, T2, T3, T4, T5);
+    args_impl!(T1, T2, T3, T4, T5, T6);
+
+    /// Rebuilds the receivers of a task's channel inputs, taking them in argument order.
     pub trait ArgsState {
-        fn downcast(receivers: Vec<Box<dyn Any>>) -> Self;
+        fn downcast(receivers: &mut dyn Iterator<Item = Box<dyn Any>>) -> Self;
+        /// Whether every channel input has a value waiting.
+        fn ready(&self) -> bool;
+        /// Whether a channel input has been closed and emptied, so it'll never be ready.
+        fn closed(&self) -> bool;
+        /// Drops one value from every channel input, in place of a run.
+        fn skip(&self);
     }
     impl ArgsState for () {
-        fn downcast(receivers: Vec<Box<dyn Any>>) -> Self {
-            assert!(receivers.len() == 0);
-            ()
+        fn downcast(receivers: &mut dyn Iterator<Item = Box<dyn Any>>) -> Self {}
+        fn ready(&self) -> bool {
+            true
+        }
+        fn closed(&self) -> bool {
+            false
         }
+        fn skip(&self) {}
     }
-    impl<T: 'static> ArgsState for (Receiver<T>) {
-        fn downcast(mut receivers: Vec<Box<dyn Any>>) -> Self {
-            assert!(receivers.len() == 1);
-            *receivers.pop().unwrap().downcast().expect("cringe")
+    impl<T: 'static> ArgsState for Receiver<T> {
+        fn downcast(receivers: &mut dyn Iterator<Item = Box<dyn Any>>) -> Self {
+            *receivers
+                .next()
+                .expect("Missing receiver")
+                .downcast()
+                .expect("cringe")
+        }
+        fn ready(&self) -> bool {
+            !self.is_empty()
+        }
+        fn closed(&self) -> bool {
+            self.is_terminated()
+        }
+        fn skip(&self) {
+            self.try_recv().ok();
         }
     }
 
     pub struct RwGuards<'a> {
-        pub(crate) refs: Vec<&'a RwLock<Box<dyn Any>>>,
+        pub(crate) graph: &'a StableDiGraph<Node, Edge>,
+        /// The task's resource edges, in argument order.
+        pub(crate) leases: &'a [(NodeIndex, Access)],
+        pub(crate) next: usize,
+        pub(crate) tracer: Option<&'a Tracer>,
+        /// The task being polled.
+        pub(crate) task: NodeIndex,
+    }
+    impl<'a> RwGuards<'a> {
+        /// The lock for the next resource argument.
+        pub(crate) fn next(&mut self) -> &'a Resource {
+            let (resource, _) = self.leases[self.next];
+            self.next += 1;
+            let Node::Resource(lock) = &self.graph[resource] else {
+                unreachable!("Leases are only taken on resources");
+            };
+            lock
+        }
     }
     pub struct Edge {
         pub(crate) arg_idx: usize,
         pub(crate) meta: Access,
+        /// Set for channel inputs wrapped in [`Buffered`].
+        pub(crate) channel: Option<Channel>,
+        /// Set for the arms of a branch, which are only sent outputs they match.
+        pub(crate) route: Option<Route>,
+    }
+    impl<I: Args> Args for After<I> {
+        type Data<'a> = I::Data<'a>;
+        type Receivers = I::Receivers;
+        fn get_edge_info(&self) -> Vec<(NodeHandle, Edge)> {
+            let mut edges = self.args.get_edge_info();
+            for &handle in &self.after {
+                edges.push((
+                    handle,
+                    Edge {
+                        arg_idx: usize::MAX,
+                        meta: Access::Order,
+                        channel: None,
+                        route: None,
+                    },
+                ));
+            }
+            edges
+        }
+        fn prepare_inputs<'a>(
+            state: &Self::Receivers,
+            ctx: &mut RwGuards<'a>,
+        ) -> Result<Self::Data<'a>, ReceiveError> {
+            I::prepare_inputs(state, ctx)
+        }
     }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub enum Access {
         Consume,
+        /// A channel input that shares the output rather than taking it.
+        Share,
         Read,
         Write,
+        /// Ordering only - no data or lease changes hands.
+        Order,
     }
 }
 #[derive(Debug)]
@@ -257,40 +603,104 @@ pub enum ReceiveError {
     Closed,
     Empty,
     WouldBlock,
+    /// A task panicked while it was writing to the resource.
+    Poisoned,
 }
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug)]
 pub struct TaskHandle<T> {
     pub(crate) idx: NodeIndex,
+    pub(crate) generation: u32,
     pub(crate) _marker: PhantomData<T>,
 }
+// Handles are Copy whatever T is, so these can't be derived.
+impl<T> Clone for TaskHandle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<T> Copy for TaskHandle<T> {}
+impl<T> From<TaskHandle<T>> for NodeIndex {
+    fn from(handle: TaskHandle<T>) -> Self {
+        handle.idx
+    }
+}
+impl<T> From<TaskHandle<T>> for NodeHandle {
+    fn from(handle: TaskHandle<T>) -> Self {
+        Self {
+            idx: handle.idx,
+            generation: handle.generation,
+        }
+    }
+}
 impl<T> TaskHandle<T> {
-    fn new(idx: NodeIndex) -> Self {
+    fn new(idx: NodeIndex, generation: u32) -> Self {
         Self {
             idx,
+            generation,
             _marker: PhantomData,
         }
     }
 }
-#[derive(Debug, Clone, Copy)]
+/// A [`TaskHandle`] or [`ResourceHandle`] with its type left off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeHandle {
+    pub(crate) idx: NodeIndex,
+    pub(crate) generation: u32,
+}
+#[derive(Debug)]
 pub struct ResourceHandle<T> {
     pub(crate) idx: NodeIndex,
+    pub(crate) generation: u32,
     pub(crate) _marker: PhantomData<T>,
 }
+// Handles are Copy whatever T is, so these can't be derived.
+impl<T> Clone for ResourceHandle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<T> Copy for ResourceHandle<T> {}
+impl<T> From<ResourceHandle<T>> for NodeIndex {
+    fn from(handle: ResourceHandle<T>) -> Self {
+        handle.idx
+    }
+}
+impl<T> From<ResourceHandle<T>> for NodeHandle {
+    fn from(handle: ResourceHandle<T>) -> Self {
+        Self {
+            idx: handle.idx,
+            generation: handle.generation,
+        }
+    }
+}
 impl<T> ResourceHandle<T> {
-    fn new(idx: NodeIndex) -> Self {
+    fn new(idx: NodeIndex, generation: u32) -> Self {
         Self {
             idx,
+            generation,
             _marker: PhantomData,
         }
     }
 }
+/// Task arguments plus tasks that have to finish first. Built by [`Args::after`].
 #[derive(Debug)]
-pub struct Read<T>(T);
+pub struct After<I> {
+    args: I,
+    after: Vec<NodeHandle>,
+}
+impl<I> After<I> {
+    pub fn after<T>(mut self, task: TaskHandle<T>) -> Self {
+        self.after.push(task.into());
+        self
+    }
+}
 #[derive(Debug)]
-pub struct Write<T>(T);
+pub struct Read<T>(pub T);
+#[derive(Debug)]
+pub struct Write<T>(pub T);
 #[derive(Debug)]
 pub struct ReadGuard<'a, T> {
-    guard: RwLockReadGuard<'a, Box<dyn Any>>,
+    guard: RwLockReadGuard<'a, AnyBox>,
     _marker: PhantomData<&'a T>,
 }
 impl<'a, T: 'static> Deref for ReadGuard<'a, T> {
@@ -303,7 +713,7 @@ impl<'a, T: 'static> Deref for ReadGuard<'a, T> {
 }
 #[derive(Debug)]
 pub struct WriteGuard<'a, T> {
-    guard: RwLockWriteGuard<'a, Box<dyn Any>>,
+    guard: RwLockWriteGuard<'a, AnyBox>,
     _marker: PhantomData<&'a T>,
 }
 impl<'a, T: 'static> Deref for WriteGuard<'a, T> {
@@ -314,6 +724,17 @@ impl<'a, T: 'static> Deref for WriteGuard<'a, T> {
             .expect("Task scheduled with incorrect arguments. CRITICAL LIBRARY BUG")
     }
 }
+#[derive(Debug)]
+pub struct OutputGuard<'a, T> {
+    output: Arc<T>,
+    _marker: PhantomData<&'a T>,
+}
+impl<'a, T> Deref for OutputGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        &self.output
+    }
+}
 impl<'a, T: 'static> DerefMut for WriteGuard<'a, T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         self.guard
@@ -322,77 +743,361 @@ impl<'a, T: 'static> DerefMut for WriteGuard<'a, T> {
     }
 }
 
+pub(crate) type AnyBox = Box<dyn Any + Send + Sync>;
+
 pub(crate) enum NodeType {
     Task,
     Resource,
 }
 pub(crate) enum Node {
     Task(Box<dyn TaskNode>),
-    Resource(RwLock<Box<dyn Any>>),
+    Resource(Resource),
 }
 impl Node {
     pub(crate) fn task<F, I, O>(f: F, receivers: I::Receivers) -> Self
     where
-        F: for<'a> Fn(I::Data<'a>) -> O + 'static,
+        F: TaskFn<I, O> + 'static,
         I: Args + 'static,
-        O: Clone + 'static,
+        O: Send + Sync + 'static,
     {
         let td = TaskData::<F, I, O>::new(f, receivers);
         Node::Task(Box::new(td))
     }
 
-    pub(crate) fn resource<T: Any>(t: T) -> Self {
-        Node::Resource(RwLock::new(Box::new(t)))
+    pub(crate) fn resource<T: Any + Send + Sync>(t: T) -> Self {
+        Node::Resource(Resource::new(Box::new(t)))
     }
 }
-pub(crate) trait TaskNode {
-    fn poll(&self, ctx: RwGuards) -> Result<(), ReceiveError>;
-    // ehh. TODO.
-    fn receiver(&mut self) -> Box<dyn Any>;
+pub(crate) trait TaskNode: Send + Sync {
+    fn poll(&self, ctx: RwGuards) -> Result<(), PollError>;
+    /// Whether the task has all of its channel inputs, and isn't held back by a full
+    /// output channel.
+    fn runnable(&self) -> bool;
+    /// Whether one of the task's channel inputs was closed from the sending side.
+    fn inputs_closed(&self) -> bool;
+    /// Whether the task was added with [`Plan::add_source`] or one of its variants.
+    fn is_source(&self) -> bool;
+    /// Whether the task waits on the other end of an [`Inlet`] or [`Outlet`].
+    fn external(&self) -> bool;
+    /// Lets the outside world know a stream has ended.
+    fn end_stream(&self);
+    /// Whether the task was added with [`Plan::add_pure_task`].
+    fn is_pure(&self) -> bool;
+    /// Which `add_*` the task was added with.
+    fn kind(&self) -> TaskKind;
+    /// How many times the task has produced an output.
+    fn version(&self) -> u64;
+    /// Stands in for a run by passing on the last output again, and dropping the inputs
+    /// that were meant for this run. Returns false if there's no output to pass on.
+    fn replay(&self) -> bool;
+    /// Opens a new output channel to `consumer`, set up as `edge` asks. Consumers that
+    /// take the output by value count towards whether it's kept or only lent out, see
+    /// [`Slot`].
+    fn receiver(&mut self, consumer: NodeIndex, edge: &Edge) -> Box<dyn Any>;
+    /// Closes the channels to `consumer`.
+    fn disconnect(&mut self, consumer: NodeIndex);
+    /// Takes the task's output channels, a `Vec<Outgoing<Arc<O>>>`, to hand on to a
+    /// task replacing it.
+    fn take_outgoing(&mut self) -> Box<dyn Any>;
+    /// Whether one of the channels to `consumer` is empty.
+    fn drained(&self, consumer: NodeIndex) -> bool;
+    /// Drops any outputs still sitting in this task's outgoing channels. Channels set up
+    /// with [`Buffered`] are only emptied if `buffered` is set.
+    fn discard_outputs(&self, buffered: bool);
+    /// Drops any outputs still sitting in this task's unbuffered channels to `consumer`.
+    fn discard_outputs_to(&self, consumer: NodeIndex);
+    /// The `Mutex<Slot<O>>` holding the task's latest output.
+    fn output(&self) -> &dyn Any;
+}
+
+pub type BoxFuture<'a, O> = Pin<Box<dyn Future<Output = O> + 'a>>;
+/// The arguments of an async task. Wrapping them ties the future's lifetime to the
+/// leases it borrows from.
+pub struct Inputs<'a, I: Args>(pub I::Data<'a>);
+
+/// The body of a task, called once the task's inputs have been prepared.
+pub(crate) trait TaskFn<I: Args, O>: Send + Sync {
+    fn call(&self, args: I::Data<'_>) -> Result<O, PollError>;
+    fn is_source(&self) -> bool {
+        false
+    }
+    fn external(&self) -> bool {
+        false
+    }
+    /// Whether the outside end of an external task has room, or something to hand over.
+    fn ready(&self) -> bool {
+        true
+    }
+    /// Whether the outside end of an external task has gone away.
+    fn closed(&self) -> bool {
+        false
+    }
+    fn end_stream(&self) {}
+    fn is_pure(&self) -> bool {
+        false
+    }
+    fn kind(&self) -> TaskKind {
+        TaskKind::Plain
+    }
+}
+pub(crate) struct SyncFn<F>(F);
+impl<F, I, O> TaskFn<I, O> for SyncFn<F>
+where
+    F: for<'a> Fn(I::Data<'a>) -> O + Send + Sync,
+    I: Args,
+{
+    fn call(&self, args: I::Data<'_>) -> Result<O, PollError> {
+        Ok((self.0)(args))
+    }
+}
+pub(crate) struct AsyncFn<F> {
+    f: F,
+    runtime: tokio::runtime::Handle,
+}
+impl<F, I, O> TaskFn<I, O> for AsyncFn<F>
+where
+    F: for<'a> Fn(Inputs<'a, I>) -> BoxFuture<'a, O> + Send + Sync,
+    I: Args,
+{
+    fn call(&self, args: I::Data<'_>) -> Result<O, PollError> {
+        Ok(block_on(&self.runtime, (self.f)(Inputs(args))))
+    }
+    fn kind(&self) -> TaskKind {
+        TaskKind::Async
+    }
+}
+
+/// Drives `future` to completion on the calling thread, with `runtime` entered so its
+/// timers and IO are served by the runtime's own threads. Unlike `Handle::block_on`,
+/// this also works from inside another runtime - `execute` called from async code. On
+/// a rayon worker it picks up other work while the future waits, so the async tasks
+/// `execute_parallel` starts overlap however small the pool is.
+fn block_on<F: Future>(runtime: &tokio::runtime::Handle, future: F) -> F::Output {
+    struct Unpark(std::thread::Thread);
+    impl std::task::Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+    let _entered = runtime.enter();
+    let waker = std::task::Waker::from(Arc::new(Unpark(std::thread::current())));
+    let mut cx = std::task::Context::from_waker(&waker);
+    let mut future = std::pin::pin!(future);
+    loop {
+        if let std::task::Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        if rayon::yield_now() != Some(rayon::Yield::Executed) {
+            std::thread::park();
+        }
+    }
 }
 
 pub(crate) struct TaskData<F, I: Args, O> {
     pub(crate) f: F,
     pub(crate) receivers: I::Receivers,
-    pub(crate) senders: Vec<kanal::Sender<O>>,
+    pub(crate) outgoing: Vec<Outgoing<Arc<O>>>,
+    pub(crate) output: Mutex<Slot<O>>,
+    pub(crate) version: AtomicU64,
 }
 impl<F, I, O> TaskNode for TaskData<F, I, O>
 where
-    F: for<'a> Fn(I::Data<'a>) -> O,
+    F: TaskFn<I, O>,
     I: Args,
-    O: Clone + 'static,
+    O: Send + Sync + 'static,
 {
-    fn poll(&self, mut ctx: RwGuards) -> Result<(), ReceiveError> {
-        let args = I::prepare_inputs(&self.receivers, &mut ctx)?;
-        let ret = (self.f)(args);
-        for sender in &self.senders {
-            sender.send(ret.clone()).ok();
+    fn poll(&self, mut ctx: RwGuards) -> Result<(), PollError> {
+        if self.outgoing.iter().any(Outgoing::rejects) {
+            return Err(PollError::ChannelFull);
         }
+        let acquiring = std::time::Instant::now();
+        let args = I::prepare_inputs(&self.receivers, &mut ctx);
+        ctx.trace(SpanKind::Acquire, acquiring);
+        let args = args?;
+        let ret = match self.f.call(args) {
+            Ok(ret) => ret,
+            Err(PollError::Failed(e)) => {
+                *self.output.lock().unwrap() = Slot::Empty;
+                return Err(PollError::Failed(e));
+            }
+            Err(e) => return Err(e),
+        };
+        let ret = Arc::new(ret);
+        // Pure tasks hold on to their output, in case they're replayed.
+        let lent = !self.f.is_pure() && self.outgoing.iter().any(|o| o.by_value);
+        *self.output.lock().unwrap() = match lent {
+            false => Slot::Kept(ret.clone()),
+            true => Slot::Lent(Arc::downgrade(&ret)),
+        };
+        self.version.fetch_add(1, Ordering::AcqRel);
+        let sending = std::time::Instant::now();
+        for outgoing in &self.outgoing {
+            if outgoing.routes(&*ret) {
+                outgoing.send(ret.clone());
+            }
+        }
+        ctx.trace(SpanKind::Send, sending);
         Ok(())
     }
-    fn receiver(&mut self) -> Box<dyn Any> {
-        let (sender, receiver) = kanal::bounded::<O>(10);
-        self.senders.push(sender);
+    fn runnable(&self) -> bool {
+        self.receivers.ready()
+            && self.f.ready()
+            && !self.outgoing.iter().any(Outgoing::backpressured)
+    }
+    fn inputs_closed(&self) -> bool {
+        self.receivers.closed() || self.f.closed()
+    }
+    fn is_source(&self) -> bool {
+        self.f.is_source()
+    }
+    fn external(&self) -> bool {
+        self.f.external()
+    }
+    fn end_stream(&self) {
+        self.f.end_stream()
+    }
+    fn is_pure(&self) -> bool {
+        self.f.is_pure()
+    }
+    fn kind(&self) -> TaskKind {
+        self.f.kind()
+    }
+    fn version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
+    fn replay(&self) -> bool {
+        if self.outgoing.iter().any(Outgoing::rejects) {
+            return false;
+        }
+        let Some(output) = self.output.lock().unwrap().get() else {
+            return false;
+        };
+        self.receivers.skip();
+        for outgoing in &self.outgoing {
+            if outgoing.routes(&*output) {
+                outgoing.send(output.clone());
+            }
+        }
+        true
+    }
+    fn receiver(&mut self, consumer: NodeIndex, edge: &Edge) -> Box<dyn Any> {
+        let Channel { capacity, overflow } = edge.channel.unwrap_or_default();
+        let (sender, receiver) = kanal::bounded::<Arc<O>>(capacity);
+        self.outgoing.push(Outgoing {
+            sender,
+            drain: receiver.clone(),
+            consumer,
+            by_value: edge.meta == Access::Consume,
+            overflow,
+            buffered: edge.channel.is_some(),
+            route: edge.route,
+        });
         Box::new(receiver)
     }
+    fn disconnect(&mut self, consumer: NodeIndex) {
+        self.outgoing
+            .retain(|outgoing| outgoing.consumer != consumer);
+    }
+    fn take_outgoing(&mut self) -> Box<dyn Any> {
+        Box::new(std::mem::take(&mut self.outgoing))
+    }
+    fn drained(&self, consumer: NodeIndex) -> bool {
+        self.outgoing
+            .iter()
+            .any(|outgoing| outgoing.consumer == consumer && outgoing.drain.is_empty())
+    }
+    fn discard_outputs(&self, buffered: bool) {
+        for outgoing in &self.outgoing {
+            if buffered || !outgoing.buffered {
+                while let Ok(Some(_)) = outgoing.drain.try_recv() {}
+            }
+        }
+    }
+    fn discard_outputs_to(&self, consumer: NodeIndex) {
+        for outgoing in &self.outgoing {
+            if outgoing.consumer == consumer && !outgoing.buffered {
+                while let Ok(Some(_)) = outgoing.drain.try_recv() {}
+            }
+        }
+    }
+    fn output(&self) -> &dyn Any {
+        &self.output
+    }
 }
 impl<F, I, O> TaskData<F, I, O>
 where
-    F: for<'a> Fn(I::Data<'a>) -> O + 'static,
+    F: TaskFn<I, O>,
     I: Args,
-    O: Clone,
 {
     pub(crate) fn new(f: F, receivers: I::Receivers) -> Self {
         Self {
             f,
             receivers,
-            senders: vec![],
+            outgoing: vec![],
+            output: Mutex::new(Slot::Empty),
+            version: AtomicU64::new(0),
         }
     }
 }
 #[derive(Debug)]
 pub enum ExecutionError {
     CyclicGraph,
+    /// The feedback edge doesn't close a loop (`to` can't reach `from`), its loop is fed
+    /// by a channel from outside the loop, or it partially overlaps another loop.
+    InvalidFeedbackEdge {
+        from: NodeIndex,
+        to: NodeIndex,
+    },
+    /// Two tasks access `resource`, at least one of them writing, and nothing in the
+    /// graph orders them. `first` is the one that was added first. The labels are the
+    /// nodes' own, or a default naming their kind and index.
+    ConflictingAccess {
+        first: NodeIndex,
+        second: NodeIndex,
+        resource: NodeIndex,
+        first_label: String,
+        second_label: String,
+        resource_label: String,
+    },
+    /// A task added with [`Plan::add_fallible_task`] returned an error.
+    TaskFailed {
+        task: NodeIndex,
+        label: Option<String>,
+        error: BoxError,
+    },
+    /// A task's [`Overflow::Error`] channel was full, so it wasn't run.
+    ChannelFull {
+        task: NodeIndex,
+        label: Option<String>,
+    },
+    /// The task passed to `execute_until` was held back by backpressure, or by a task it
+    /// takes input from being held back, running dry or taking another branch.
+    Throttled {
+        task: NodeIndex,
+        label: Option<String>,
+    },
+    /// A task panicked. Its panic is handled like a failure, see [`FailurePolicy`].
+    TaskPanicked {
+        task: NodeIndex,
+        label: Option<String>,
+        message: String,
+    },
+    /// `task` takes `resource`, which was poisoned by an earlier panic.
+    ResourcePoisoned {
+        resource: NodeIndex,
+        label: Option<String>,
+        task: NodeIndex,
+    },
+    /// `task` took input from a node that's since been removed.
+    MissingInput {
+        task: NodeIndex,
+        label: Option<String>,
+    },
+    /// A replayed `task` came out differently from the recording.
+    Diverged {
+        task: NodeIndex,
+        label: Option<String>,
+    },
 }
 
 #[cfg(test)]
@@ -416,11 +1121,112 @@ mod test {
         graph.execute().unwrap();
     }
 
+    #[test]
+    fn test_async_write_across_await() {
+        let mut graph = Executor::new();
+        let buf = graph.add_resource(1);
+        graph.add_async_task(Write(buf), |Inputs(mut buf)| {
+            Box::pin(async move {
+                *buf += 1;
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+                *buf *= 10;
+            })
+        });
+        graph.execute().unwrap();
+        assert_eq!(*graph.get(buf).unwrap(), 20);
+    }
+    #[test]
+    fn test_async_feeds_sync_task() {
+        let mut graph = Executor::new();
+        let value = graph.add_resource(4);
+        let out = std::sync::Arc::new(std::sync::Mutex::new(0));
+        let fetched = graph.add_async_task(Read(value), |Inputs(x)| {
+            Box::pin(async move {
+                tokio::task::yield_now().await;
+                *x + 1
+            })
+        });
+        let store = out.clone();
+        graph.add_task(fetched, move |x| *store.lock().unwrap() = x * 3);
+        graph.execute_parallel().unwrap();
+        assert_eq!(*out.lock().unwrap(), 15);
+    }
+
+    #[tokio::test]
+    async fn test_execute_inside_runtime() {
+        let mut graph = Executor::new();
+        let log = graph.add_resource(vec![]);
+        let fetched = graph.add_async_task((), |Inputs(())| {
+            Box::pin(async move {
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+                7
+            })
+        });
+        graph.add_task((fetched, Write(log)), |(v, mut log)| log.push(v));
+        graph.execute().unwrap();
+        graph.execute_parallel().unwrap();
+        assert_eq!(*graph.get(log).unwrap(), [7, 7]);
+    }
+
+    #[test]
+    fn test_async_tasks_overlap() {
+        let mut graph = Executor::new();
+        let in_flight = Arc::new(AtomicU64::new(0));
+        let most = Arc::new(AtomicU64::new(0));
+        for _ in 0..4 {
+            let (in_flight, most) = (in_flight.clone(), most.clone());
+            graph.add_async_task((), move |Inputs(())| {
+                let (in_flight, most) = (in_flight.clone(), most.clone());
+                Box::pin(async move {
+                    let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    most.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                })
+            });
+        }
+        graph.execute_parallel().unwrap();
+        assert!(most.load(Ordering::SeqCst) > 1);
+    }
+
+    #[test]
+    fn test_after_orders_without_data() {
+        let mut graph = Executor::new();
+        let particle = graph.add_resource((10, 0));
+        let gravity = graph.add_task(Write(particle), |mut p| p.1 -= 2);
+        let velocity = graph.add_task(Write(particle).after(gravity), |mut p| p.0 += p.1);
+        let log = graph.add_resource(vec![]);
+        let done = graph.add_task(().after(velocity), |()| "done");
+        graph.add_task(Write(log).after(gravity).after(done), |mut log| {
+            log.push("logged")
+        });
+        graph.execute().unwrap();
+        assert_eq!(*graph.get(particle).unwrap(), (8, -2));
+        graph.execute_parallel().unwrap();
+        assert_eq!(*graph.get(particle).unwrap(), (4, -4));
+        assert_eq!(*graph.get(log).unwrap(), ["logged", "logged"]);
+    }
+
+    #[test]
+    fn test_order_built_tasks() {
+        let mut graph = Executor::new();
+        let particle = graph.add_resource((10, 0));
+        let velocity = graph.add_task(Write(particle), |mut p| p.0 += p.1);
+        let gravity = graph.add_task(Write(particle), |mut p| p.1 -= 2);
+        assert!(matches!(
+            graph.execute(),
+            Err(ExecutionError::ConflictingAccess { .. })
+        ));
+        graph.add_order(gravity, velocity);
+        graph.execute().unwrap();
+        graph.execute_parallel().unwrap();
+        assert_eq!(*graph.get(particle).unwrap(), (4, -4));
+    }
+
     fn times_int_by_two(it: i32) -> i32 {
         it * 2
     }
 
-    // Failing - need to figure out how I want to get graph outputs.
     #[test]
     fn test_simple_linear_chain() {
         let mut graph = Executor::new();
@@ -428,39 +1234,38 @@ mod test {
         let initial_value = graph.add_resource(10i32);
         let plus_five = graph.add_task(Read(initial_value), |x| *x + 5);
         let times_two = graph.add_task(plus_five, times_int_by_two);
-        //let writer = graph.add_task((times_two, Write(initial_value)), |(x, r)| *r = x);
+        let writer = graph.add_task((times_two, Write(initial_value)), |(x, mut r)| *r = x);
         graph.execute().unwrap();
         let result = *graph.get(initial_value).unwrap();
         // (10 + 5) * 2 == 30
         assert_eq!(result, 30);
     }
 
-    /* Requires Args impl
     #[test]
     fn test_diamond_execution() {
         let mut graph = Executor::new();
 
         let initial_value = graph.add_resource(10i32);
+        let result = graph.add_resource(String::new());
         let plus_five = graph.add_task(Read(initial_value), |x| *x + 5);
         let times_two = graph.add_task(Read(initial_value), |x| *x * 2);
-        let to_string = graph.add_task((Read(plus_five), Read(times_two)), |(x, y)| {
-            format!("{} + {} = {}", x, y, x + y)
+        let to_string = graph.add_task((plus_five, times_two, Write(result)), |(x, y, mut out)| {
+            *out = format!("{} + {} = {}", x, y, x + y)
         });
 
-        let result = LinearExecutor.execute(&graph, to_string).unwrap();
-
-        assert_eq!(result, "15 + 20 = 35");
+        graph.execute().unwrap();
+        assert_eq!(*graph.get(result).unwrap(), "15 + 20 = 35");
+        graph.execute_parallel().unwrap();
+        assert_eq!(*graph.get(result).unwrap(), "15 + 20 = 35");
     }
-    */
 
-    /* Requires Args impl
     #[test]
     fn test_read_write_dependency() {
         let mut graph = Executor::new();
 
         let val_handle = graph.add_resource(100i32);
 
-        let increment_stage = graph.add_task(Write(val_handle), |x| {
+        let increment_stage = graph.add_task(Write(val_handle), |mut x| {
             *x += 10;
         });
 
@@ -476,18 +1281,63 @@ mod test {
         // 100 + 10 = 110
         assert_eq!(result, 110);
     }
-    */
 
-    /* Requires Args impl
+    #[test]
+    fn test_mixed_args_order() {
+        let mut graph = Executor::new();
+        let a = graph.add_resource(1);
+        let b = graph.add_resource(10);
+        let out = graph.add_resource(vec![]);
+        let three = graph.add_task((), |()| 3);
+        let four = graph.add_task((), |()| 4);
+        graph.add_task(
+            (Read(a), three, Write(out), four, Read(b)),
+            |(a, three, mut out, four, b)| out.extend([*a, three, four, *b]),
+        );
+        graph.execute().unwrap();
+        assert_eq!(*graph.get(out).unwrap(), [1, 3, 4, 10]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_aliased_write_panics() {
+        let mut graph = Executor::new();
+        let a = graph.add_resource(1);
+        graph.add_task((Read(a), Write(a)), |_| ());
+    }
+
     #[test]
     fn test_no_input_stage() {
         let mut graph = Executor::new();
 
         let generate_forty_two = graph.add_task((), |_| 42);
         graph.execute().unwrap();
-        let result = *graph.get(generate_forty_two).unwrap();
+        let result = *graph.output(generate_forty_two).unwrap();
 
         assert_eq!(result, 42);
     }
-    */
+
+    #[test]
+    fn test_execute_until() {
+        let mut graph = Executor::new();
+
+        let initial_value = graph.add_resource(10i32);
+        let plus_five = graph.add_task(Read(initial_value), |x| *x + 5);
+        let times_two = graph.add_task(plus_five, times_int_by_two);
+        let unrelated = graph.add_task((), |_| "unrelated");
+        let downstream = graph.add_task(times_two, |x| x - 1);
+
+        // (10 + 5) * 2 == 30
+        assert_eq!(graph.execute_until(times_two).unwrap(), 30);
+        assert!(graph.output(times_two).is_none());
+        assert!(graph.output(unrelated).is_none());
+        assert!(graph.output(downstream).is_none());
+
+        *graph.get_mut(initial_value).unwrap() = 0;
+        graph.execute().unwrap();
+        // times_two's output was moved into downstream, its only consumer.
+        assert!(graph.output(times_two).is_none());
+        assert_eq!(graph.take_output(downstream), Some(9));
+        assert_eq!(graph.take_output(downstream), None);
+    }
 }
@@ -0,0 +1,165 @@
+use super::*;
+
+/// A resource's data, and a count of the times it's been handed out for writing.
+pub(crate) struct Resource {
+    lock: RwLock<AnyBox>,
+    version: AtomicU64,
+}
+impl Resource {
+    pub(crate) fn new(data: AnyBox) -> Self {
+        Self {
+            lock: RwLock::new(data),
+            version: AtomicU64::new(0),
+        }
+    }
+    pub(crate) fn bump(&self) {
+        self.version.fetch_add(1, Ordering::AcqRel);
+    }
+    pub(crate) fn version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
+    /// The data, even if a panic poisoned it.
+    pub(crate) fn into_inner(self) -> AnyBox {
+        self.lock
+            .into_inner()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+impl Deref for Resource {
+    type Target = RwLock<AnyBox>;
+    fn deref(&self) -> &RwLock<AnyBox> {
+        &self.lock
+    }
+}
+
+pub(crate) struct PureFn<F>(pub(crate) F);
+impl<F, I, O> TaskFn<I, O> for PureFn<F>
+where
+    F: for<'a> Fn(I::Data<'a>) -> O + Send + Sync,
+    I: Args,
+{
+    fn call(&self, args: I::Data<'_>) -> Result<O, PollError> {
+        Ok((self.0)(args))
+    }
+    fn is_pure(&self) -> bool {
+        true
+    }
+    fn kind(&self) -> TaskKind {
+        TaskKind::Pure
+    }
+}
+
+pub(crate) fn assert_pure(handles: &impl Args) {
+    let writes = handles
+        .get_edge_info()
+        .iter()
+        .any(|(_, edge)| edge.meta == Access::Write);
+    assert!(!writes, "A pure task can't take a resource it writes");
+}
+
+impl Plan {
+    /// Adds a task whose output only depends on its inputs. A run skips it when none of
+    /// them have changed since it last ran - no resource it reads was written, and the
+    /// tasks it takes input from were skipped too - and passes its last output on again.
+    /// Pure tasks can't `Write`, and keep their output, so consumers that take it by
+    /// value always get a clone.
+    pub fn add_pure_task<F, I, O, D>(&mut self, handles: I, f: F) -> TaskHandle<O>
+    where
+        F: for<'a> Fn(I::Data<'a>) -> O + Send + Sync + 'static,
+        O: Send + Sync + 'static,
+        I: Args<Receivers = D> + 'static,
+        D: ArgsState,
+    {
+        assert_pure(&handles);
+        self.add_task_node(handles, PureFn(f))
+    }
+
+    /// How many times the resource has been handed out for writing, by a task's `Write`
+    /// argument or [`Plan::get_mut`]. Unchanged means nothing can have written to it.
+    pub fn version<T>(&self, resource_handle: ResourceHandle<T>) -> u64 {
+        let Some(resource) = self.resource_of(resource_handle) else {
+            return 0;
+        };
+        resource.version()
+    }
+
+    /// The versions of everything `task` takes input from, to compare against the ones
+    /// it last ran on.
+    pub(crate) fn input_versions(&self, task: NodeIndex) -> Vec<u64> {
+        self.graph
+            .edges_directed(task, petgraph::Direction::Incoming)
+            .filter(|edge| edge.weight().meta != Access::Order)
+            .map(|edge| match &self.graph[edge.source()] {
+                Node::Resource(resource) => resource.version(),
+                Node::Task(producer) => producer.version(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn test_pure_tasks_skip_unchanged_inputs() {
+        let mut graph = Executor::new();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let text = graph.add_resource("styx".to_string());
+        let scale = graph.add_resource(2);
+        let counter = runs.clone();
+        let length = graph.add_pure_task(Read(text), move |text| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            text.len()
+        });
+        let counter = runs.clone();
+        let scaled = graph.add_pure_task((length, Read(scale)), move |(length, scale)| {
+            counter.fetch_add(10, Ordering::SeqCst);
+            length * *scale
+        });
+        let preview = graph.add_resource(0);
+        graph.add_task((scaled, Write(preview)), |(scaled, mut preview)| {
+            *preview = scaled
+        });
+
+        graph.execute().unwrap();
+        graph.execute().unwrap();
+        graph.execute_parallel().unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 11);
+        assert_eq!(*graph.get(preview).unwrap(), 8);
+
+        // Only what's downstream of the change runs again.
+        *graph.get_mut(scale).unwrap() = 3;
+        graph.execute().unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 21);
+        assert_eq!(*graph.get(preview).unwrap(), 12);
+
+        let before = graph.version(text);
+        graph.get_mut(text).unwrap().push_str("-rs");
+        assert_eq!(graph.version(text), before + 1);
+        graph.execute_parallel().unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 32);
+        assert_eq!(*graph.get(preview).unwrap(), 21);
+    }
+
+    #[test]
+    fn test_writes_bump_versions() {
+        let mut graph = Executor::new();
+        let value = graph.add_resource(0);
+        graph.add_task(Write(value), |mut v| *v += 1);
+        graph.execute().unwrap();
+        graph.execute().unwrap();
+        assert_eq!(graph.version(value), 2);
+        graph.replace_resource(value, 10);
+        assert_eq!(graph.version(value), 3);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_pure_task_cant_write() {
+        let mut graph = Executor::new();
+        let value = graph.add_resource(0);
+        graph.add_pure_task(Write(value), |_| ());
+    }
+}
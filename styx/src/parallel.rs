@@ -0,0 +1,303 @@
+use super::*;
+use std::{
+    collections::{HashMap, HashSet},
+    time::Instant,
+};
+
+impl Plan {
+    /// Runs the graph on the rayon thread pool. A task is queued once every task it
+    /// depends on has finished, and is only started once the scheduler can grant all
+    /// of its resource leases - any number of `Read`s, or a single `Write`.
+    pub(crate) fn run_parallel(&self, compiled: &Compiled) -> Result<(), ExecutionError> {
+        let scheduler = Scheduler {
+            plan: self,
+            compiled,
+        };
+        let ready = {
+            let mut state = compiled.scheduler.lock().unwrap();
+            state.reset(compiled);
+            state.take_runnable(self, &compiled.tasks)
+        };
+        rayon::in_place_scope(|scope| {
+            for (node, leased) in ready {
+                scheduler.spawn(scope, node, leased);
+            }
+        });
+        let mut state = compiled.scheduler.lock().unwrap();
+        self.finish_run(compiled, &mut state.failures)
+    }
+}
+
+struct Scheduler<'g> {
+    plan: &'g Plan,
+    compiled: &'g Compiled,
+}
+
+/// The scheduler's bookkeeping for one run. It lives in the [`Compiled`] schedule and is
+/// reset at the start of each run, so its allocations are reused.
+#[derive(Default)]
+pub(crate) struct SchedulerState {
+    /// Number of upstream tasks that still have to finish, per task.
+    pending: HashMap<NodeIndex, usize>,
+    /// Dependencies that have already been counted off, so a task repeated by a loop
+    /// doesn't release its dependents outside the loop more than once.
+    satisfied: HashSet<(NodeIndex, NodeIndex)>,
+    /// Tasks whose dependencies are met, waiting on a lease, in the order they became
+    /// ready, and when they did.
+    waiting: Vec<(NodeIndex, Instant)>,
+    leases: HashMap<NodeIndex, Lease>,
+    /// Passes completed so far by each loop.
+    passes: Vec<usize>,
+    failures: Failures,
+}
+
+#[derive(Default, Clone, Copy)]
+struct Lease {
+    readers: usize,
+    writer: bool,
+}
+
+impl<'g> Scheduler<'g> {
+    /// Runs `node` on the pool. Tasks that weren't `leased` had no chance of running, so
+    /// they're only completed.
+    fn spawn<'s>(&'s self, scope: &rayon::Scope<'s>, node: NodeIndex, leased: bool) {
+        scope.spawn(move |scope| {
+            let result = match leased {
+                true => self.plan.poll_task(self.compiled, node),
+                false => Ok(Polled::Held),
+            };
+            let ready = {
+                let mut state = self.compiled.scheduler.lock().unwrap();
+                if leased {
+                    state.release(&self.compiled.tasks[&node]);
+                }
+                let ran = match result {
+                    Ok(Polled::Held) => {
+                        state.failures.held.insert(node);
+                        false
+                    }
+                    Ok(polled) => polled == Polled::Ran,
+                    Err(e) => {
+                        self.plan.fail(&mut state.failures, node, e);
+                        false
+                    }
+                };
+                self.complete(&mut state, node, ran);
+                state.take_runnable(self.plan, &self.compiled.tasks)
+            };
+            for (node, leased) in ready {
+                self.spawn(scope, node, leased);
+            }
+        });
+    }
+
+    /// Counts `node` off its dependents, unless a loop ending at `node` starts another
+    /// pass - then the loop's tasks are queued again instead. Loops only go round again
+    /// if `node` actually ran. Dependents that can't run because of a failure are
+    /// completed straight away, without running.
+    fn complete(&self, state: &mut SchedulerState, node: NodeIndex, ran: bool) {
+        for (i, l) in self.compiled.loops.iter().enumerate() {
+            let feedback = &self.plan.feedback[l.feedback];
+            if feedback.from != node {
+                continue;
+            }
+            state.passes[i] += 1;
+            let failed = l.region.iter().any(|n| state.failures.failed.contains(n));
+            let until = || feedback.until.as_ref().is_some_and(|until| until());
+            if ran && !failed && state.passes[i] < feedback.max_iterations && !until() {
+                self.plan.discard_outputs(&l.region, true);
+                self.restart(state, i);
+                return;
+            }
+            state.passes[i] = 0;
+        }
+        for dependent in &self.compiled.tasks[&node].dependents {
+            if state.satisfied.insert((node, *dependent)) {
+                let pending = state.pending.get_mut(dependent).unwrap();
+                *pending -= 1;
+                if *pending == 0 && self.plan.blocked(&state.failures, *dependent) {
+                    state.failures.failed.insert(*dependent);
+                    self.complete(state, *dependent, false);
+                } else if *pending == 0 {
+                    state.waiting.push((*dependent, Instant::now()));
+                }
+            }
+        }
+    }
+
+    /// Re-arms every task in the loop, as if none of the loop had run yet.
+    fn restart(&self, state: &mut SchedulerState, i: usize) {
+        let region = &self.compiled.loops[i].region;
+        for (j, l) in self.compiled.loops.iter().enumerate() {
+            if j != i && l.region.iter().all(|node| region.contains(node)) {
+                state.passes[j] = 0;
+            }
+        }
+        for node in region {
+            let mut pending = 0;
+            for dependency in &self.compiled.tasks[node].dependencies {
+                if region.contains(dependency) {
+                    state.satisfied.remove(&(*dependency, *node));
+                    pending += 1;
+                }
+            }
+            state.pending.insert(*node, pending);
+            if pending == 0 {
+                state.waiting.push((*node, Instant::now()));
+            }
+        }
+    }
+}
+
+impl SchedulerState {
+    fn reset(&mut self, compiled: &Compiled) {
+        self.pending.clear();
+        self.satisfied.clear();
+        self.waiting.clear();
+        self.leases.clear();
+        self.passes.clear();
+        self.failures.clear();
+        self.passes.resize(compiled.loops.len(), 0);
+        for node in &compiled.order {
+            let dependencies = compiled.tasks[node].dependencies.len();
+            self.pending.insert(*node, dependencies);
+            if dependencies == 0 {
+                self.waiting.push((*node, Instant::now()));
+            }
+        }
+    }
+
+    /// Grants leases to every waiting task that can currently take all of them, and
+    /// returns those tasks. Leases are taken all-or-nothing, so tasks can't deadlock.
+    /// Tasks missing a channel input - skipped by a branch, say - are returned too, but
+    /// without leases, so they don't hold anything up.
+    fn take_runnable(
+        &mut self,
+        plan: &Plan,
+        tasks: &HashMap<NodeIndex, CompiledTask>,
+    ) -> Vec<(NodeIndex, bool)> {
+        let mut runnable = vec![];
+        if self.failures.cancelled {
+            return runnable;
+        }
+        let mut i = 0;
+        while i < self.waiting.len() {
+            let node = self.waiting[i].0;
+            let Node::Task(t) = &plan.graph[node] else {
+                unreachable!("Only tasks are scheduled");
+            };
+            let task = &tasks[&node];
+            if !t.runnable() {
+                self.waiting.remove(i);
+                runnable.push((node, false));
+            } else if self.can_lease(task) {
+                self.acquire(task);
+                let (node, queued) = self.waiting.remove(i);
+                if let Some(tracer) = &plan.tracer {
+                    tracer.record(node, SpanKind::Wait, queued);
+                }
+                runnable.push((node, true));
+            } else {
+                i += 1;
+            }
+        }
+        runnable
+    }
+
+    fn can_lease(&self, task: &CompiledTask) -> bool {
+        task.leases.iter().all(|(resource, access)| {
+            let lease = self.leases.get(resource).copied().unwrap_or_default();
+            match access {
+                Access::Read => !lease.writer,
+                Access::Write => !lease.writer && lease.readers == 0,
+                Access::Consume | Access::Share | Access::Order => true,
+            }
+        })
+    }
+
+    fn acquire(&mut self, task: &CompiledTask) {
+        for (resource, access) in &task.leases {
+            let lease = self.leases.entry(*resource).or_default();
+            match access {
+                Access::Read => lease.readers += 1,
+                Access::Write => lease.writer = true,
+                Access::Consume | Access::Share | Access::Order => {}
+            }
+        }
+    }
+
+    fn release(&mut self, task: &CompiledTask) {
+        for (resource, access) in &task.leases {
+            let lease = self.leases.get_mut(resource).unwrap();
+            match access {
+                Access::Read => lease.readers -= 1,
+                Access::Write => lease.writer = false,
+                Access::Consume | Access::Share | Access::Order => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        sync::Arc,
+        time::Duration,
+    };
+
+    #[test]
+    fn test_parallel_linear_chain() {
+        let mut graph = Executor::new();
+        let result = Arc::new(AtomicUsize::new(0));
+        let value = graph.add_resource(10usize);
+        let plus_five = graph.add_task(Read(value), |x| *x + 5);
+        let times_two = graph.add_task(plus_five, |x| x * 2);
+        let store = result.clone();
+        graph.add_task(times_two, move |x| store.store(x, Ordering::SeqCst));
+        graph.execute_parallel().unwrap();
+        assert_eq!(result.load(Ordering::SeqCst), 30);
+    }
+
+    #[test]
+    fn test_parallel_reads_overlap() {
+        let mut graph = Executor::new();
+        let shared = graph.add_resource(0u8);
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        for _ in 0..4 {
+            let running = running.clone();
+            let peak = peak.clone();
+            graph.add_task(Read(shared), move |_| {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(50));
+                running.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+        graph.execute_parallel().unwrap();
+        if rayon::current_num_threads() > 1 {
+            assert!(peak.load(Ordering::SeqCst) > 1);
+        }
+    }
+
+    #[test]
+    fn test_parallel_writes_exclusive() {
+        let mut graph = Executor::new();
+        graph.set_hazard_policy(HazardPolicy::Allow);
+        let counter = graph.add_resource(0usize);
+        let running = Arc::new(AtomicUsize::new(0));
+        for _ in 0..8 {
+            let running = running.clone();
+            graph.add_task(Write(counter), move |mut c| {
+                assert_eq!(running.fetch_add(1, Ordering::SeqCst), 0);
+                std::thread::sleep(Duration::from_millis(5));
+                *c += 1;
+                running.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+        graph.execute_parallel().unwrap();
+        assert_eq!(*graph.get(counter).unwrap(), 8);
+    }
+}
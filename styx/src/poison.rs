@@ -0,0 +1,200 @@
+use super::*;
+use std::{panic::AssertUnwindSafe, sync::PoisonError};
+
+impl Plan {
+    /// Whether a task panicked while it held a `Write` lease on the resource. Tasks that
+    /// take a poisoned resource fail with [`ExecutionError::ResourcePoisoned`] until it's
+    /// recovered with [`Plan::clear_poison`] or [`Plan::replace_resource`].
+    pub fn is_poisoned<T>(&self, resource_handle: ResourceHandle<T>) -> bool {
+        let Some(resource) = self.resource_of(resource_handle) else {
+            return false;
+        };
+        resource.is_poisoned()
+    }
+
+    /// Marks the resource as healthy again and hands out its data as it was left, so it
+    /// can be checked or repaired first.
+    pub fn clear_poison<T>(
+        &mut self,
+        resource_handle: ResourceHandle<T>,
+    ) -> Option<WriteGuard<'_, T>> {
+        let resource = self.resource_of(resource_handle)?;
+        resource.clear_poison();
+        self.get_mut(resource_handle)
+    }
+
+    /// Swaps in a new value for the resource, poisoned or not, and returns the old one.
+    pub fn replace_resource<T: Any + Send + Sync>(
+        &mut self,
+        resource_handle: ResourceHandle<T>,
+        data: T,
+    ) -> Option<T> {
+        let resource = self.resource_of(resource_handle)?;
+        let mut guard = resource.write().unwrap_or_else(PoisonError::into_inner);
+        let old = std::mem::replace(&mut *guard, Box::new(data));
+        drop(guard);
+        resource.clear_poison();
+        resource.bump();
+        old.downcast().ok().map(|old| *old)
+    }
+
+    /// Marks the resource as poisoned, as if a task had panicked while writing to it -
+    /// for data found to be corrupt by other means. Tasks that take it fail with
+    /// [`ExecutionError::ResourcePoisoned`] until it's recovered. Returns false if the
+    /// resource has been removed.
+    pub fn poison<T>(&mut self, resource_handle: ResourceHandle<T>) -> bool {
+        let Some(resource) = self.resource_of(resource_handle) else {
+            return false;
+        };
+        // Unwinding past a write guard is the only way to poison a lock. Resuming the
+        // unwind rather than panicking skips the panic hook, so nothing is printed.
+        let _ = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            let _guard = resource.write();
+            std::panic::resume_unwind(Box::new(()));
+        }));
+        true
+    }
+
+    /// The error for `task` finding one of its resources poisoned.
+    pub(crate) fn poisoned(&self, compiled: &Compiled, task: NodeIndex) -> ExecutionError {
+        let resource = compiled.tasks[&task]
+            .leases
+            .iter()
+            .map(|(resource, _)| *resource)
+            .find(|resource| {
+                matches!(&self.graph[*resource], Node::Resource(lock) if lock.is_poisoned())
+            })
+            .expect("A task only sees poisoning through its own leases");
+        ExecutionError::ResourcePoisoned {
+            resource,
+            label: self.labels.get(&resource).cloned(),
+            task,
+        }
+    }
+}
+
+/// Best effort at getting the message out of a panic payload.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<dyn Any>".to_string()
+    }
+}
+
+impl Schedule {
+    pub fn poison<T>(&mut self, resource_handle: ResourceHandle<T>) -> bool {
+        self.plan.poison(resource_handle)
+    }
+    pub fn clear_poison<T>(
+        &mut self,
+        resource_handle: ResourceHandle<T>,
+    ) -> Option<WriteGuard<'_, T>> {
+        self.plan.clear_poison(resource_handle)
+    }
+    pub fn replace_resource<T: Any + Send + Sync>(
+        &mut self,
+        resource_handle: ResourceHandle<T>,
+        data: T,
+    ) -> Option<T> {
+        self.plan.replace_resource(resource_handle, data)
+    }
+}
+
+impl Executor {
+    // Like get_mut, these skip DerefMut so recovering doesn't throw away the schedule.
+    pub fn poison<T>(&mut self, resource_handle: ResourceHandle<T>) -> bool {
+        self.plan.poison(resource_handle)
+    }
+    pub fn clear_poison<T>(
+        &mut self,
+        resource_handle: ResourceHandle<T>,
+    ) -> Option<WriteGuard<'_, T>> {
+        self.plan.clear_poison(resource_handle)
+    }
+    pub fn replace_resource<T: Any + Send + Sync>(
+        &mut self,
+        resource_handle: ResourceHandle<T>,
+        data: T,
+    ) -> Option<T> {
+        self.plan.replace_resource(resource_handle, data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn panicking_graph() -> (Executor, ResourceHandle<Vec<i32>>) {
+        let mut graph = Executor::new();
+        let values = graph.add_resource(vec![1, 2, 3]);
+        graph.set_label(values, "values");
+        let sum = graph.add_task(Write(values), |mut values| {
+            values.push(4);
+            assert!(values.len() < 5, "too many values");
+            values.iter().sum::<i32>()
+        });
+        graph.set_label(sum, "sum");
+        (graph, values)
+    }
+
+    #[test]
+    fn test_panic_is_reported() {
+        let (mut graph, values) = panicking_graph();
+        graph.execute().unwrap();
+        let Err(ExecutionError::TaskPanicked { label, message, .. }) = graph.execute() else {
+            panic!("Panic wasn't caught");
+        };
+        assert_eq!(label.as_deref(), Some("sum"));
+        assert_eq!(message, "too many values");
+        assert!(graph.is_poisoned(values));
+        assert!(graph.get(values).is_none());
+        for result in [graph.execute(), graph.execute_parallel()] {
+            let Err(ExecutionError::ResourcePoisoned {
+                resource, label, ..
+            }) = result
+            else {
+                panic!("Poisoned resource was used");
+            };
+            assert_eq!((resource, label.as_deref()), (values.idx, Some("values")));
+        }
+    }
+
+    #[test]
+    fn test_recover_poisoned_resource() {
+        let (mut graph, values) = panicking_graph();
+        graph.execute().unwrap();
+        assert!(graph.execute_parallel().is_err());
+        {
+            let mut data = graph.clear_poison(values).unwrap();
+            assert_eq!(*data, vec![1, 2, 3, 4, 4]);
+            data.truncate(1);
+        }
+        assert!(!graph.is_poisoned(values));
+        graph.execute().unwrap();
+        assert_eq!(graph.replace_resource(values, vec![]), Some(vec![1, 4]));
+        graph.execute_parallel().unwrap();
+        assert_eq!(*graph.get(values).unwrap(), vec![4]);
+    }
+
+    #[test]
+    fn test_poison_blocks_readers() {
+        let mut graph = Executor::new();
+        let values = graph.add_resource(vec![1, 2]);
+        let sum = graph.add_task(Read(values), |v| v.iter().sum::<i32>());
+        graph.execute().unwrap();
+        assert!(graph.poison(values));
+        assert!(graph.is_poisoned(values));
+        for result in [graph.execute(), graph.execute_parallel()] {
+            let Err(ExecutionError::ResourcePoisoned { resource, task, .. }) = result else {
+                panic!("The poisoned resource was read");
+            };
+            assert_eq!((resource, task), (values.idx, sum.idx));
+        }
+        graph.clear_poison(values).unwrap().push(3);
+        graph.execute().unwrap();
+        assert_eq!(*graph.output(sum).unwrap(), 6);
+    }
+}
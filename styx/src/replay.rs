@@ -0,0 +1,435 @@
+use super::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// Everything recorded between [`Plan::start_recording`] and [`Plan::stop_recording`],
+/// one entry per run. It's serializable, so a surprising run can be saved and replayed
+/// somewhere else.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Recording {
+    pub runs: Vec<RecordedRun>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RecordedRun {
+    /// In the order they happened. Polls are ordered by when they started, which under
+    /// `execute_parallel` is an order the tasks could have run in one at a time: tasks
+    /// that overlapped didn't share a channel or a resource either of them wrote.
+    pub events: Vec<Event>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Event {
+    /// A task with all of its inputs was polled. Tasks that were held back or skipped
+    /// aren't recorded.
+    Poll {
+        /// The task's node index.
+        task: usize,
+        outcome: Outcome,
+        /// The output it sent its consumers, if it's registered with
+        /// [`Plan::record_output`].
+        output: Option<serde_json::Value>,
+        /// The resources it took a `Write` lease on.
+        writes: Vec<Transition>,
+    },
+    /// The outputs queued by these tasks were dropped, as at the start of a loop's next
+    /// pass.
+    Discard { tasks: Vec<usize>, buffered: bool },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Outcome {
+    Ran,
+    Held,
+    Closed,
+    Failed,
+}
+impl From<&Result<Polled, ExecutionError>> for Outcome {
+    fn from(result: &Result<Polled, ExecutionError>) -> Self {
+        match result {
+            Ok(Polled::Ran) => Outcome::Ran,
+            Ok(Polled::Held) => Outcome::Held,
+            Ok(Polled::Closed) => Outcome::Closed,
+            Err(_) => Outcome::Failed,
+        }
+    }
+}
+
+/// A resource's version before and after a poll.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transition {
+    pub resource: usize,
+    pub from: u64,
+    pub to: u64,
+}
+
+/// Saves and restores the output of a task registered with [`Plan::record_output`].
+pub(crate) struct OutputCodec {
+    save: fn(&dyn Any) -> Option<serde_json::Value>,
+    load: fn(&dyn Any, serde_json::Value) -> serde_json::Result<()>,
+}
+impl OutputCodec {
+    fn new<T: Serialize + DeserializeOwned + Send + Sync + 'static>() -> Self {
+        Self {
+            save: |output| {
+                let output = downcast_slot::<T>(output).lock().unwrap().get()?;
+                // An output that can't be written is left out, and the task runs on
+                // replay instead.
+                serde_json::to_value(&*output).ok()
+            },
+            load: |output, value| {
+                let value = serde_json::from_value::<T>(value)?;
+                *downcast_slot::<T>(output).lock().unwrap() = Slot::Kept(Arc::new(value));
+                Ok(())
+            },
+        }
+    }
+}
+fn downcast_slot<T: 'static>(output: &dyn Any) -> &Mutex<Slot<T>> {
+    output
+        .downcast_ref()
+        .expect("Output registered with incorrect codec. CRITICAL LIBRARY BUG")
+}
+
+#[derive(Default)]
+pub(crate) struct Recorder {
+    events: Mutex<Vec<Event>>,
+    runs: Mutex<Vec<RecordedRun>>,
+}
+impl Recorder {
+    /// Records a poll of `node`, in the order it started.
+    pub(crate) fn record(
+        &self,
+        plan: &Plan,
+        compiled: &Compiled,
+        node: NodeIndex,
+        poll: impl FnOnce() -> Result<Polled, ExecutionError>,
+    ) -> Result<Polled, ExecutionError> {
+        let before = plan.write_versions(compiled, node);
+        let index = {
+            let mut events = self.events.lock().unwrap();
+            events.push(Event::Discard {
+                tasks: vec![],
+                buffered: false,
+            });
+            events.len() - 1
+        };
+        let result = poll();
+        let outcome = Outcome::from(&result);
+        let output = match (outcome, plan.output_codecs.get(&node)) {
+            (Outcome::Ran, Some(codec)) => (codec.save)(plan.task_node(node).output()),
+            _ => None,
+        };
+        let writes = before
+            .into_iter()
+            .zip(plan.write_versions(compiled, node))
+            .map(|((resource, from), (_, to))| Transition {
+                resource: resource.index(),
+                from,
+                to,
+            })
+            .collect();
+        self.events.lock().unwrap()[index] = Event::Poll {
+            task: node.index(),
+            outcome,
+            output,
+            writes,
+        };
+        result
+    }
+    pub(crate) fn discard(&self, tasks: &[NodeIndex], buffered: bool) {
+        self.events.lock().unwrap().push(Event::Discard {
+            tasks: tasks.iter().map(|task| task.index()).collect(),
+            buffered,
+        });
+    }
+    /// Drops every mention of a removed node, so a recording doesn't replay it as
+    /// whatever takes its index next.
+    pub(crate) fn forget(&self, node: NodeIndex) {
+        let mut events = self.events.lock().unwrap();
+        let mut runs = self.runs.lock().unwrap();
+        let events = runs
+            .iter_mut()
+            .map(|run| &mut run.events)
+            .chain(std::iter::once(&mut *events));
+        for events in events {
+            events.retain_mut(|event| match event {
+                Event::Poll { task, writes, .. } => {
+                    writes.retain(|write| write.resource != node.index());
+                    *task != node.index()
+                }
+                Event::Discard { tasks, .. } => {
+                    tasks.retain(|task| *task != node.index());
+                    true
+                }
+            });
+        }
+    }
+    pub(crate) fn end_run(&self) {
+        let events = std::mem::take(&mut *self.events.lock().unwrap());
+        self.runs.lock().unwrap().push(RecordedRun { events });
+    }
+}
+
+impl Plan {
+    /// Records the output of `task` while recording, so it's fed to the task's consumers
+    /// on replay instead of running the task again. Meant for tasks that don't give the
+    /// same output twice - reading the clock, a random number generator or a device.
+    /// Any resources such a task writes aren't written on replay.
+    pub fn record_output<T>(&mut self, task: TaskHandle<T>)
+    where
+        T: Serialize + DeserializeOwned + Send + Sync + 'static,
+    {
+        assert!(
+            self.task_of(task).is_some(),
+            "Only tasks still in the graph can have their outputs recorded"
+        );
+        self.output_codecs.insert(task.idx, OutputCodec::new::<T>());
+    }
+
+    /// Starts recording every run: which tasks were polled in what order, what came of
+    /// each, the outputs of tasks registered with [`Plan::record_output`], and how the
+    /// versions of the resources they wrote moved.
+    pub fn start_recording(&mut self) {
+        self.recorder.get_or_insert_with(Recorder::default);
+    }
+
+    /// Stops recording and hands back what was recorded. A run that was cut short
+    /// counts as a run of its own.
+    pub fn stop_recording(&mut self) -> Recording {
+        let Some(recorder) = self.recorder.take() else {
+            return Recording::default();
+        };
+        if !recorder.events.lock().unwrap().is_empty() {
+            recorder.end_run();
+        }
+        Recording {
+            runs: recorder.runs.into_inner().unwrap(),
+        }
+    }
+
+    /// Runs the graph exactly as recorded: every poll one at a time in recorded order,
+    /// with recorded outputs fed in place of their tasks. Fails with
+    /// [`ExecutionError::Diverged`] as soon as a task comes out differently - it ran
+    /// where it was held, failed where it didn't, or wrote different resources.
+    ///
+    /// A replay isn't recorded, even while recording is on - otherwise replaying that
+    /// recording would play the run twice.
+    pub(crate) fn replay(
+        &mut self,
+        compiled: &Compiled,
+        run: &RecordedRun,
+    ) -> Result<(), ExecutionError> {
+        let recorder = self.recorder.take();
+        let result = self.replay_run(compiled, run);
+        self.recorder = recorder;
+        result
+    }
+
+    fn replay_run(&self, compiled: &Compiled, run: &RecordedRun) -> Result<(), ExecutionError> {
+        let mut failures = Failures::default();
+        for event in &run.events {
+            let (task, outcome, output, writes) = match event {
+                Event::Discard { tasks, buffered } => {
+                    let tasks: Vec<_> = tasks.iter().map(|task| NodeIndex::new(*task)).collect();
+                    if let Some(&task) = tasks.iter().find(|t| !compiled.tasks.contains_key(t)) {
+                        return Err(ExecutionError::Diverged {
+                            task,
+                            label: self.labels.get(&task).cloned(),
+                        });
+                    }
+                    self.discard_outputs(&tasks, *buffered);
+                    continue;
+                }
+                Event::Poll {
+                    task,
+                    outcome,
+                    output,
+                    writes,
+                } => (NodeIndex::new(*task), *outcome, output, writes),
+            };
+            let diverged = || ExecutionError::Diverged {
+                task,
+                label: self.labels.get(&task).cloned(),
+            };
+            if !compiled.tasks.contains_key(&task) {
+                return Err(diverged());
+            }
+            let before = self.write_versions(compiled, task);
+            let result = match (output, self.output_codecs.get(&task)) {
+                (Some(output), Some(codec)) => {
+                    let node = self.task_node(task);
+                    (codec.load)(node.output(), output.clone()).map_err(|_| diverged())?;
+                    match node.replay() {
+                        true => Ok(Polled::Ran),
+                        false => Ok(Polled::Held),
+                    }
+                }
+                _ => self.poll_task(compiled, task),
+            };
+            if Outcome::from(&result) != outcome {
+                return Err(diverged());
+            }
+            if output.is_none() {
+                let moved = before
+                    .into_iter()
+                    .zip(self.write_versions(compiled, task))
+                    .map(|((_, from), (_, to))| to - from);
+                if !moved.eq(writes.iter().map(|write| write.to - write.from)) {
+                    return Err(diverged());
+                }
+            }
+            match result {
+                Ok(Polled::Held) => {
+                    failures.held.insert(task);
+                }
+                Ok(_) => {}
+                Err(e) => self.fail(&mut failures, task, e),
+            }
+        }
+        self.finish_run(compiled, &mut failures)
+    }
+
+    /// The versions of the resources `task` writes.
+    fn write_versions(&self, compiled: &Compiled, task: NodeIndex) -> Vec<(NodeIndex, u64)> {
+        compiled.tasks[&task]
+            .leases
+            .iter()
+            .filter(|(_, access)| *access == Access::Write)
+            .map(|(resource, _)| match &self.graph[*resource] {
+                Node::Resource(lock) => (*resource, lock.version()),
+                Node::Task(_) => unreachable!("Leases are only taken on resources"),
+            })
+            .collect()
+    }
+
+    fn task_node(&self, node: NodeIndex) -> &dyn TaskNode {
+        match &self.graph[node] {
+            Node::Task(task) => &**task,
+            Node::Resource(_) => unreachable!("Only tasks are scheduled"),
+        }
+    }
+}
+
+impl Schedule {
+    /// Replays one run of a [`Recording`] made on this graph, or one built the same way.
+    pub fn replay(&mut self, run: &RecordedRun) -> Result<(), ExecutionError> {
+        self.plan.replay(&self.compiled, run)
+    }
+}
+
+impl Executor {
+    pub fn replay(&mut self, run: &RecordedRun) -> Result<(), ExecutionError> {
+        self.compile()?;
+        let compiled = self.compiled.as_ref().unwrap();
+        self.plan.replay(compiled, run)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Two writers race for a log under `execute_parallel`, fed by a source that never
+    /// gives the same value twice.
+    fn racy_graph(seed: Arc<AtomicU64>) -> (Executor, ResourceHandle<Vec<u64>>) {
+        let mut graph = Executor::new();
+        graph.set_hazard_policy(HazardPolicy::Allow);
+        let log = graph.add_resource(vec![]);
+        let noise = graph.add_task((), move |()| seed.fetch_add(7, Ordering::SeqCst));
+        graph.record_output(noise);
+        graph.add_task((noise, Write(log)), |(n, mut log)| log.push(n));
+        graph.add_task((noise, Write(log)), |(n, mut log)| log.push(n + 1000));
+        (graph, log)
+    }
+
+    #[test]
+    fn test_replay_reproduces_run() {
+        let (mut graph, log) = racy_graph(Arc::new(AtomicU64::new(1)));
+        graph.start_recording();
+        graph.execute_parallel().unwrap();
+        graph.execute_parallel().unwrap();
+        let recording = graph.stop_recording();
+        assert_eq!(recording.runs.len(), 2);
+        let recorded = graph.get(log).unwrap().clone();
+
+        // A fresh graph whose source would give different values.
+        let (mut copy, copy_log) = racy_graph(Arc::new(AtomicU64::new(500)));
+        for run in &recording.runs {
+            copy.replay(run).unwrap();
+        }
+        assert_eq!(*copy.get(copy_log).unwrap(), recorded);
+
+        // Replaying while recording leaves the new recording untouched.
+        copy.start_recording();
+        copy.replay(&recording.runs[0]).unwrap();
+        copy.execute().unwrap();
+        let again = copy.stop_recording();
+        assert_eq!(again.runs.len(), 1);
+        assert_eq!(again.runs[0].events.len(), recording.runs[0].events.len());
+
+        let saved = serde_json::to_string(&recording).unwrap();
+        let loaded: Recording = serde_json::from_str(&saved).unwrap();
+        assert_eq!(loaded, recording);
+    }
+
+    #[test]
+    fn test_recording_tracks_writes() {
+        let mut graph = Executor::new();
+        let value = graph.add_resource(0);
+        let step = graph.add_task(Write(value), |mut v| *v += 1);
+        graph.add_task(step, |()| ());
+        graph.start_recording();
+        graph.execute().unwrap();
+        let recording = graph.stop_recording();
+        let [Event::Poll { task, writes, .. }, Event::Poll { outcome, .. }] =
+            &recording.runs[0].events[..]
+        else {
+            panic!("Unexpected events {:?}", recording.runs[0].events);
+        };
+        assert_eq!(*task, step.idx.index());
+        assert_eq!(
+            writes[..],
+            [Transition {
+                resource: value.idx.index(),
+                from: 0,
+                to: 1
+            }]
+        );
+        assert_eq!(*outcome, Outcome::Ran);
+    }
+
+    #[test]
+    fn test_replay_detects_divergence() {
+        let mut graph = Executor::new();
+        let value = graph.add_resource(1);
+        let check = graph.add_fallible_task(Read(value), |v| match *v {
+            1 => Ok(*v),
+            _ => Err(std::io::Error::other("not one")),
+        });
+        graph.set_label(check, "check");
+        graph.start_recording();
+        graph.execute().unwrap();
+        let recording = graph.stop_recording();
+        *graph.get_mut(value).unwrap() = 2;
+        let Err(ExecutionError::Diverged { task, label }) = graph.replay(&recording.runs[0]) else {
+            panic!("The divergence wasn't caught");
+        };
+        assert_eq!((task, label.as_deref()), (check.idx, Some("check")));
+    }
+
+    #[test]
+    fn test_replay_rejects_unknown_discard() {
+        let mut graph = Executor::new();
+        graph.add_task((), |()| 1);
+        let run = RecordedRun {
+            events: vec![Event::Discard {
+                tasks: vec![7],
+                buffered: false,
+            }],
+        };
+        let Err(ExecutionError::Diverged { task, .. }) = graph.replay(&run) else {
+            panic!("The unknown task wasn't caught");
+        };
+        assert_eq!(task, NodeIndex::new(7));
+    }
+}
@@ -0,0 +1,397 @@
+use super::*;
+use parallel::SchedulerState;
+use std::{
+    collections::{HashMap, HashSet},
+    panic::AssertUnwindSafe,
+    sync::atomic::{AtomicU64, Ordering},
+    sync::Arc,
+    time::Instant,
+};
+
+/// Everything about a graph that doesn't change between runs, worked out once.
+pub(crate) struct Compiled {
+    /// Every task, in an order that respects dependencies and feedback loops.
+    pub(crate) order: Vec<NodeIndex>,
+    pub(crate) loops: Vec<Loop>,
+    pub(crate) tasks: HashMap<NodeIndex, CompiledTask>,
+    pub(crate) scheduler: Mutex<SchedulerState>,
+}
+
+pub(crate) struct CompiledTask {
+    /// Resource edges, in argument order.
+    pub(crate) leases: Vec<(NodeIndex, Access)>,
+    /// Tasks that have to finish first, including the end of any loop this task waits on.
+    pub(crate) dependencies: Vec<NodeIndex>,
+    pub(crate) dependents: Vec<NodeIndex>,
+    /// Nanoseconds the last poll took, `u64::MAX` until the task has run.
+    pub(crate) last_run: AtomicU64,
+    /// For pure tasks, the versions of the inputs they last ran on.
+    pub(crate) memo: Mutex<Option<Vec<u64>>>,
+}
+
+impl Compiled {
+    pub(crate) fn new(plan: &Plan) -> Result<Self, ExecutionError> {
+        let graph = &plan.graph;
+        plan.check_inputs()?;
+        let loops = plan.loops()?;
+        let mut order_graph = plan.order_graph(&loops);
+        plan.resolve_hazards(&loops, &mut order_graph)?;
+        let order: Vec<_> = petgraph::algo::toposort(&order_graph, None)
+            .map_err(|_| ExecutionError::CyclicGraph)?
+            .into_iter()
+            .filter(|node| matches!(graph[*node], Node::Task(_)))
+            .collect();
+        let mut tasks = HashMap::new();
+        for &node in &order {
+            let mut leases: Vec<_> = graph
+                .edges_directed(node, petgraph::Direction::Incoming)
+                .filter(|edge| matches!(graph[edge.source()], Node::Resource(_)))
+                .map(|edge| (edge.source(), edge.weight()))
+                .collect();
+            leases.sort_unstable_by_key(|(_, edge)| edge.arg_idx);
+            let mut dependencies = vec![];
+            for dependency in order_graph.neighbors_directed(node, petgraph::Direction::Incoming) {
+                let is_task = matches!(graph[dependency], Node::Task(_));
+                if is_task && !dependencies.contains(&dependency) {
+                    dependencies.push(dependency);
+                }
+            }
+            let mut dependents = vec![];
+            for dependent in order_graph.neighbors_directed(node, petgraph::Direction::Outgoing) {
+                if !dependents.contains(&dependent) {
+                    dependents.push(dependent);
+                }
+            }
+            tasks.insert(
+                node,
+                CompiledTask {
+                    leases: leases
+                        .into_iter()
+                        .map(|(resource, edge)| (resource, edge.meta))
+                        .collect(),
+                    dependencies,
+                    dependents,
+                    last_run: AtomicU64::new(u64::MAX),
+                    memo: Mutex::new(None),
+                },
+            );
+        }
+        Ok(Self {
+            order,
+            loops,
+            tasks,
+            scheduler: Mutex::new(SchedulerState::default()),
+        })
+    }
+}
+
+/// What came of polling a task that didn't fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Polled {
+    Ran,
+    /// Missing a channel input, or held back by backpressure.
+    Held,
+    /// A source ran dry, or one of its channel inputs was closed.
+    Closed,
+}
+
+impl Plan {
+    pub(crate) fn run(&self, compiled: &Compiled) -> Result<(), ExecutionError> {
+        let mut failures = Failures::default();
+        self.run_sequential(compiled, &compiled.order, &mut vec![], &mut failures);
+        self.finish_run(compiled, &mut failures)
+    }
+
+    pub(crate) fn run_sequential(
+        &self,
+        compiled: &Compiled,
+        nodes: &[NodeIndex],
+        active: &mut Vec<usize>,
+        failures: &mut Failures,
+    ) {
+        for &node in nodes {
+            if failures.cancelled {
+                return;
+            }
+            if self.blocked(failures, node) {
+                failures.failed.insert(node);
+                continue;
+            }
+            match self.poll_task(compiled, node) {
+                Ok(Polled::Ran) => self.close_loops(compiled, node, active, failures),
+                Ok(Polled::Held) => {
+                    failures.held.insert(node);
+                }
+                Ok(Polled::Closed) => {}
+                Err(e) => self.fail(failures, node, e),
+            }
+        }
+    }
+
+    /// Polls a task, catching its panics. A panic while writing to a resource poisons it.
+    /// Pure tasks whose inputs haven't changed are replayed rather than run.
+    pub(crate) fn poll_task(
+        &self,
+        compiled: &Compiled,
+        node: NodeIndex,
+    ) -> Result<Polled, ExecutionError> {
+        let Node::Task(task) = &self.graph[node] else {
+            unreachable!("Only tasks are scheduled");
+        };
+        if !task.runnable() {
+            return Ok(Polled::Held);
+        }
+        match &self.recorder {
+            Some(recorder) => recorder.record(self, compiled, node, || {
+                self.poll_runnable(compiled, node, &**task)
+            }),
+            None => self.poll_runnable(compiled, node, &**task),
+        }
+    }
+
+    /// Polls a task that has everything it needs to run.
+    fn poll_runnable(
+        &self,
+        compiled: &Compiled,
+        node: NodeIndex,
+        task: &dyn TaskNode,
+    ) -> Result<Polled, ExecutionError> {
+        let compiled_task = &compiled.tasks[&node];
+        let versions = task.is_pure().then(|| self.input_versions(node));
+        if versions.is_some() {
+            let mut memo = compiled_task.memo.lock().unwrap();
+            if *memo == versions && task.replay() {
+                return Ok(Polled::Ran);
+            }
+            *memo = None;
+        }
+        let guards = RwGuards {
+            graph: &self.graph,
+            leases: &compiled_task.leases,
+            next: 0,
+            tracer: self.tracer.as_ref(),
+            task: node,
+        };
+        let start = Instant::now();
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| task.poll(guards)));
+        let elapsed = start.elapsed().as_nanos().min(u64::MAX as u128 - 1) as u64;
+        compiled_task.last_run.store(elapsed, Ordering::Relaxed);
+        if let Some(tracer) = &self.tracer {
+            tracer.record(node, SpanKind::Poll, start);
+        }
+        let label = || self.labels.get(&node).cloned();
+        match result {
+            Ok(Ok(())) => {
+                if versions.is_some() {
+                    *compiled_task.memo.lock().unwrap() = versions;
+                }
+                Ok(Polled::Ran)
+            }
+            Ok(Err(PollError::Closed)) => Ok(Polled::Closed),
+            Ok(Err(PollError::Failed(error))) => Err(ExecutionError::TaskFailed {
+                task: node,
+                label: label(),
+                error,
+            }),
+            Ok(Err(PollError::Receive(ReceiveError::Poisoned))) => {
+                Err(self.poisoned(compiled, node))
+            }
+            Ok(Err(PollError::ChannelFull)) => Err(ExecutionError::ChannelFull {
+                task: node,
+                label: label(),
+            }),
+            Ok(Err(PollError::Receive(e))) => unreachable!("Scheduled incorrectly! {:?}", e),
+            Err(payload) => Err(ExecutionError::TaskPanicked {
+                task: node,
+                label: label(),
+                message: panic_message(&*payload),
+            }),
+        }
+    }
+
+    /// Runs `handle` and only the tasks it depends on, then takes its output.
+    pub(crate) fn run_until<T: 'static>(
+        &self,
+        compiled: &Compiled,
+        handle: TaskHandle<T>,
+    ) -> Result<T, ExecutionError> {
+        let reversed = petgraph::visit::Reversed(&self.graph);
+        let mut dfs = petgraph::visit::Dfs::new(reversed, handle.idx);
+        let mut upstream = HashSet::new();
+        while let Some(node) = dfs.next(reversed) {
+            upstream.insert(node);
+        }
+        let mut order = compiled.order.clone();
+        order.retain(|node| upstream.contains(node));
+        let Node::Task(task) = &self.graph[handle.idx] else {
+            unreachable!("TaskHandle doesn't point at a task");
+        };
+        let slot: &Mutex<Slot<T>> = task.output().downcast_ref().expect("cringe");
+        // Clear out any output from an earlier run, in case the target doesn't run.
+        *slot.lock().unwrap() = Slot::Empty;
+        let mut failures = Failures::default();
+        self.run_sequential(compiled, &order, &mut vec![], &mut failures);
+        self.finish_run(compiled, &mut failures)?;
+        let output = std::mem::replace(&mut *slot.lock().unwrap(), Slot::Empty).get();
+        // Tasks downstream of the target didn't run, so nothing should be left queued for
+        // them - unless they're buffered, and meant to catch up later. The target's own
+        // consumers lose this output either way, since it's handed back here.
+        self.discard_outputs(&order, false);
+        task.discard_outputs(true);
+        let output = output.ok_or_else(|| ExecutionError::Throttled {
+            task: handle.idx,
+            label: self.labels.get(&handle.idx).cloned(),
+        })?;
+        Ok(Arc::try_unwrap(output)
+            .ok()
+            .expect("Nothing else holds on to the output once its channels are emptied"))
+    }
+}
+
+/// A validated, compiled graph. The execution order, every task's leases and the channel
+/// wiring are fixed when the [`Plan`] is built, so running it again only does the work.
+pub struct Schedule {
+    pub(crate) plan: Plan,
+    pub(crate) compiled: Compiled,
+}
+impl Schedule {
+    pub(crate) fn new(plan: Plan, compiled: Compiled) -> Self {
+        Self { plan, compiled }
+    }
+    #[must_use = "a failed run only shows up as an `ExecutionError`"]
+    pub fn execute(&mut self) -> Result<(), ExecutionError> {
+        self.plan.run(&self.compiled)
+    }
+    #[must_use = "a failed run only shows up as an `ExecutionError`"]
+    pub fn execute_parallel(&mut self) -> Result<(), ExecutionError> {
+        self.plan.run_parallel(&self.compiled)
+    }
+    pub fn execute_until<T: 'static>(
+        &mut self,
+        handle: TaskHandle<T>,
+    ) -> Result<T, ExecutionError> {
+        self.plan.run_until(&self.compiled, handle)
+    }
+    pub fn get_mut<T>(&mut self, resource_handle: ResourceHandle<T>) -> Option<WriteGuard<'_, T>> {
+        self.plan.get_mut(resource_handle)
+    }
+    pub fn take_output<T: 'static>(&mut self, task_handle: TaskHandle<T>) -> Option<T> {
+        self.plan.take_output(task_handle)
+    }
+}
+impl Deref for Schedule {
+    type Target = Plan;
+    fn deref(&self) -> &Plan {
+        &self.plan
+    }
+}
+
+/// A [`Plan`] that builds itself the first time it's executed. The builder methods are
+/// reached through `DerefMut`, which throws the compiled schedule away, so changes to the
+/// graph are picked up on the next run.
+#[derive(Default)]
+pub struct Executor {
+    pub(crate) plan: Plan,
+    pub(crate) compiled: Option<Compiled>,
+}
+impl Executor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub(crate) fn compile(&mut self) -> Result<(&Plan, &Compiled), ExecutionError> {
+        if self.compiled.is_none() {
+            self.compiled = Some(Compiled::new(&self.plan)?);
+        }
+        Ok((&self.plan, self.compiled.as_ref().unwrap()))
+    }
+    #[must_use = "a failed run only shows up as an `ExecutionError`"]
+    pub fn execute(&mut self) -> Result<(), ExecutionError> {
+        let (plan, compiled) = self.compile()?;
+        plan.run(compiled)
+    }
+    #[must_use = "a failed run only shows up as an `ExecutionError`"]
+    pub fn execute_parallel(&mut self) -> Result<(), ExecutionError> {
+        let (plan, compiled) = self.compile()?;
+        plan.run_parallel(compiled)
+    }
+    pub fn execute_until<T: 'static>(
+        &mut self,
+        handle: TaskHandle<T>,
+    ) -> Result<T, ExecutionError> {
+        let (plan, compiled) = self.compile()?;
+        plan.run_until(compiled, handle)
+    }
+    // These skip DerefMut, so touching data doesn't throw away the schedule.
+    pub fn get_mut<T>(&mut self, resource_handle: ResourceHandle<T>) -> Option<WriteGuard<'_, T>> {
+        self.plan.get_mut(resource_handle)
+    }
+    pub fn take_output<T: 'static>(&mut self, task_handle: TaskHandle<T>) -> Option<T> {
+        self.plan.take_output(task_handle)
+    }
+}
+impl Deref for Executor {
+    type Target = Plan;
+    fn deref(&self) -> &Plan {
+        &self.plan
+    }
+}
+impl DerefMut for Executor {
+    fn deref_mut(&mut self) -> &mut Plan {
+        self.compiled = None;
+        &mut self.plan
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_plan_build_execute() {
+        let mut plan = Plan::new();
+        let particles = plan.add_resource(vec![(0i32, 1i32); 4]);
+        let gravity = plan.add_task(Write(particles), |mut pxs| {
+            pxs.iter_mut().for_each(|(_, vel)| *vel -= 1)
+        });
+        let velocity = plan.add_task(Write(particles).after(gravity), |mut pxs| {
+            pxs.iter_mut().for_each(|(pos, vel)| *pos += *vel)
+        });
+        let total = plan.add_task(Read(particles).after(velocity), |pxs| {
+            pxs.iter().map(|(pos, _)| pos).sum::<i32>()
+        });
+        let mut schedule = plan.build().unwrap();
+        for _ in 0..3 {
+            schedule.execute().unwrap();
+        }
+        schedule.execute_parallel().unwrap();
+        // Velocities go 0, -1, -2, -3 and positions sum them.
+        assert_eq!(schedule.get(particles).unwrap()[0], (-6, -3));
+        assert_eq!(*schedule.output(total).unwrap(), -24);
+        assert_eq!(schedule.execute_until(total).unwrap(), -40);
+    }
+
+    #[test]
+    fn test_plan_build_rejects_invalid_loops() {
+        let mut plan = Plan::new();
+        let a = plan.add_task((), |()| ());
+        let b = plan.add_task(a, |()| ());
+        // Feedback edges don't count as cycles, but a loop that can't be reached can't
+        // be built either.
+        plan.add_finite_feedback_edge(a, b, 2);
+        assert!(matches!(
+            plan.build(),
+            Err(ExecutionError::InvalidFeedbackEdge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_executor_recompiles_after_changes() {
+        let mut graph = Executor::new();
+        let counter = graph.add_resource(0);
+        let first = graph.add_task(Write(counter), |mut c| *c += 1);
+        graph.execute().unwrap();
+        graph.add_task(Write(counter).after(first), |mut c| *c += 10);
+        graph.execute().unwrap();
+        assert_eq!(*graph.get(counter).unwrap(), 12);
+    }
+}
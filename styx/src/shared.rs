@@ -0,0 +1,118 @@
+use super::*;
+use std::sync::{Arc, Weak};
+
+/// A channel input that shares the producer's output instead of taking it. Every
+/// `Shared` consumer gets the same `Arc`, so large outputs are never copied, and the
+/// output type doesn't have to be `Clone`.
+#[derive(Debug)]
+pub struct Shared<T>(pub TaskHandle<T>);
+
+impl<T: Send + Sync + 'static> Args for Shared<T> {
+    type Data<'a> = Arc<T>;
+    type Receivers = Receiver<Arc<T>>;
+    fn get_edge_info(&self) -> Vec<(NodeHandle, Edge)> {
+        vec![(
+            self.0.into(),
+            Edge {
+                arg_idx: 0,
+                meta: Access::Share,
+                channel: None,
+                route: None,
+            },
+        )]
+    }
+    fn prepare_inputs<'a>(
+        state: &Self::Receivers,
+        ctx: &mut RwGuards<'a>,
+    ) -> Result<Self::Data<'a>, ReceiveError> {
+        receive(state)
+    }
+}
+
+pub(crate) fn receive<T>(receiver: &Receiver<T>) -> Result<T, ReceiveError> {
+    match receiver.try_recv() {
+        Ok(Some(v)) => Ok(v),
+        Ok(None) => Err(ReceiveError::Empty),
+        Err(kanal::ReceiveError::Closed) => Err(ReceiveError::Closed),
+        Err(kanal::ReceiveError::SendClosed) => Err(ReceiveError::Closed),
+    }
+}
+
+/// A task's latest output. Tasks with a consumer that takes the output by value only
+/// lend it out, so the last consumer can move it rather than clone it.
+pub(crate) enum Slot<O> {
+    Empty,
+    Kept(Arc<O>),
+    Lent(Weak<O>),
+}
+impl<O> Slot<O> {
+    pub(crate) fn get(&self) -> Option<Arc<O>> {
+        match self {
+            Slot::Empty => None,
+            Slot::Kept(output) => Some(output.clone()),
+            Slot::Lent(output) => output.upgrade(),
+        }
+    }
+    /// Takes the output, if nothing else holds on to it.
+    pub(crate) fn take(&mut self) -> Option<O> {
+        let Slot::Kept(output) = std::mem::replace(self, Slot::Empty) else {
+            return None;
+        };
+        Arc::try_unwrap(output)
+            .map_err(|output| *self = Slot::Kept(output))
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug, PartialEq)]
+    struct Mesh(Vec<u32>);
+    impl Clone for Mesh {
+        fn clone(&self) -> Self {
+            CLONES.fetch_add(1, Ordering::SeqCst);
+            Mesh(self.0.clone())
+        }
+    }
+
+    #[test]
+    fn test_shared_and_moved_outputs() {
+        let mut graph = Executor::new();
+        let mesh = graph.add_task((), |_| Mesh(vec![1, 2, 3]));
+        let a = graph.add_task(Shared(mesh), |m| m.0.len());
+        let b = graph.add_task(Shared(mesh), |m| m.0.iter().sum::<u32>());
+        // Ordered after the shared consumers, it's left holding the only reference.
+        let moved = graph.add_task(mesh.after(a).after(b), |mut m: Mesh| {
+            m.0.push(4);
+            m
+        });
+        for _ in 0..2 {
+            graph.execute().unwrap();
+            graph.execute_parallel().unwrap();
+        }
+        assert_eq!(CLONES.load(Ordering::SeqCst), 0);
+        assert_eq!(*graph.output(a).unwrap(), 3);
+        assert_eq!(*graph.output(b).unwrap(), 6);
+        assert_eq!(graph.take_output(moved), Some(Mesh(vec![1, 2, 3, 4])));
+        // The mesh was moved into its only by-value consumer.
+        assert!(graph.output(mesh).is_none());
+    }
+
+    #[test]
+    fn test_fan_out_without_clone() {
+        struct Handle(u32);
+        let mut graph = Executor::new();
+        let handle = graph.add_task((), |_| Handle(7));
+        let doubled = graph.add_task(Shared(handle), |h| h.0 * 2);
+        graph.add_task((Shared(handle), doubled), |(h, d)| h.0 + d);
+        assert_eq!(graph.execute_until(doubled).unwrap(), 14);
+        graph.execute().unwrap();
+        assert_eq!(graph.output(handle).unwrap().0, 7);
+        assert!(graph.take_output(handle).is_some());
+    }
+}
@@ -0,0 +1,169 @@
+use super::*;
+
+/// The fields of a structure-of-arrays row. Implemented for tuples of up to six fields,
+/// each of which is stored in a column of its own.
+pub trait Row: Sized {
+    /// A `ResourceHandle<Vec<_>>` per field.
+    type Columns: Copy;
+    fn add_columns(plan: &mut Plan, rows: impl IntoIterator<Item = Self>) -> Self::Columns;
+    fn len(plan: &Plan, columns: Self::Columns) -> usize;
+    fn push(plan: &mut Plan, columns: Self::Columns, row: Self);
+    fn swap_remove(plan: &mut Plan, columns: Self::Columns, index: usize) -> Self;
+}
+
+/// A set of rows stored column by column. Every column is a resource of its own, so
+/// tasks lease columns separately: one task can `Read` positions while another `Write`s
+/// velocities of the same set, and the scheduler only orders tasks that touch the same
+/// column.
+#[derive(Debug)]
+pub struct Soa<R: Row> {
+    pub columns: R::Columns,
+}
+// Copy whatever R is, like the handles the columns are made of.
+impl<R: Row> Clone for Soa<R> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<R: Row> Copy for Soa<R> {}
+
+const POISONED: &str = "A column of the set is poisoned";
+
+macro_rules! row_impl {
+    ($($T:ident $idx:tt),+) => {
+        impl<$($T: Send + Sync + 'static),+> Row for ($($T,)+) {
+            type Columns = ($(ResourceHandle<Vec<$T>>,)+);
+            fn add_columns(plan: &mut Plan, rows: impl IntoIterator<Item = Self>) -> Self::Columns {
+                let mut columns = ($(Vec::<$T>::new(),)+);
+                for row in rows {
+                    $(columns.$idx.push(row.$idx);)+
+                }
+                ($(plan.add_resource(columns.$idx),)+)
+            }
+            fn len(plan: &Plan, columns: Self::Columns) -> usize {
+                plan.get(columns.0).expect(POISONED).len()
+            }
+            // Both check every column before touching any, so a panic leaves the set as it was.
+            fn push(plan: &mut Plan, columns: Self::Columns, row: Self) {
+                $(assert!(plan.get(columns.$idx).is_some(), "{POISONED}");)+
+                $(plan.get_mut(columns.$idx).unwrap().push(row.$idx);)+
+            }
+            fn swap_remove(plan: &mut Plan, columns: Self::Columns, index: usize) -> Self {
+                $({
+                    let len = plan.get(columns.$idx).expect(POISONED).len();
+                    assert!(index < len, "Row {index} is out of bounds for a column of {len}");
+                })+
+                ($({
+                    let mut column = plan.get_mut(columns.$idx).unwrap();
+                    column.swap_remove(index)
+                },)+)
+            }
+        }
+    };
+}
+row_impl!(T1 0);
+row_impl!(T1 0, T2 1);
+row_impl!(T1 0, T2 1, T3 2);
+row_impl!(T1 0, T2 1, T3 2, T4 3);
+row_impl!(T1 0, T2 1, T3 2, T4 3, T5 4);
+row_impl!(T1 0, T2 1, T3 2, T4 3, T5 4, T6 5);
+
+impl Plan {
+    /// Adds a structure-of-arrays set, with a `Vec` resource per field of `R`.
+    pub fn add_soa<R: Row>(&mut self, rows: impl IntoIterator<Item = R>) -> Soa<R> {
+        Soa {
+            columns: R::add_columns(self, rows),
+        }
+    }
+    /// The number of rows in the set. Tasks that push to or remove from single columns
+    /// are on their own to keep the columns the same length.
+    pub fn soa_len<R: Row>(&self, soa: Soa<R>) -> usize {
+        R::len(self, soa.columns)
+    }
+    /// Appends a row to every column of the set.
+    pub fn push_row<R: Row>(&mut self, soa: Soa<R>, row: R) {
+        R::push(self, soa.columns, row)
+    }
+    /// Removes a row from every column of the set, replacing it with the last one.
+    pub fn swap_remove_row<R: Row>(&mut self, soa: Soa<R>, index: usize) -> R {
+        R::swap_remove(self, soa.columns, index)
+    }
+}
+
+impl Schedule {
+    pub fn push_row<R: Row>(&mut self, soa: Soa<R>, row: R) {
+        self.plan.push_row(soa, row)
+    }
+    pub fn swap_remove_row<R: Row>(&mut self, soa: Soa<R>, index: usize) -> R {
+        self.plan.swap_remove_row(soa, index)
+    }
+}
+
+impl Executor {
+    // Like get_mut, these skip DerefMut, as rows don't change the graph.
+    pub fn push_row<R: Row>(&mut self, soa: Soa<R>, row: R) {
+        self.plan.push_row(soa, row)
+    }
+    pub fn swap_remove_row<R: Row>(&mut self, soa: Soa<R>, index: usize) -> R {
+        self.plan.swap_remove_row(soa, index)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::panic::AssertUnwindSafe;
+
+    #[test]
+    fn test_columns_leased_separately() {
+        let mut plan = Plan::new();
+        let particles = plan.add_soa((0..4).map(|i| (i as f32, 1.0f32, 2.0f32)));
+        let (position, velocity, mass) = particles.columns;
+        // Neither is ordered after the other, but they write different columns.
+        plan.add_par_for_each((Write(velocity), Read(mass)), 2, |(v, m)| *v -= m);
+        let momentum = plan.add_resource(0.0);
+        plan.add_task((Read(position), Write(momentum)), |(p, mut total)| {
+            *total = p.iter().sum()
+        });
+        let mut schedule = plan.build().unwrap();
+        schedule.execute_parallel().unwrap();
+        assert_eq!(*schedule.get(velocity).unwrap(), [-1.0; 4]);
+        assert_eq!(*schedule.get(momentum).unwrap(), 6.0);
+
+        schedule.push_row(particles, (4.0, 0.0, 1.0));
+        assert_eq!(schedule.swap_remove_row(particles, 0), (0.0, -1.0, 2.0));
+        assert_eq!(schedule.soa_len(particles), 4);
+        assert_eq!(*schedule.get(position).unwrap(), [4.0, 1.0, 2.0, 3.0]);
+        assert_eq!(*schedule.get(mass).unwrap(), [1.0, 2.0, 2.0, 2.0]);
+    }
+
+    #[test]
+    fn test_poisoned_column_leaves_set_intact() {
+        let mut plan = Plan::new();
+        let particles = plan.add_soa([(0, 0), (1, 1)]);
+        let (position, velocity) = particles.columns;
+        plan.poison(velocity);
+        let push = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            plan.push_row(particles, (2, 2));
+        }));
+        assert!(push.is_err());
+        let remove = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            plan.swap_remove_row(particles, 0);
+        }));
+        assert!(remove.is_err());
+        assert_eq!(*plan.get(position).unwrap(), [0, 1]);
+    }
+
+    #[test]
+    fn test_same_column_conflicts() {
+        let mut plan = Plan::new();
+        let particles = plan.add_soa([(0, 0)]);
+        let (position, velocity) = particles.columns;
+        plan.add_task(Write(position), |mut p| p[0] += 1);
+        plan.add_task((Read(velocity), Write(position)), |(v, mut p)| p[0] += v[0]);
+        let Err(ExecutionError::ConflictingAccess { resource, .. }) = plan.build() else {
+            panic!("Conflict on a column wasn't caught");
+        };
+        assert_eq!(resource, position.idx);
+    }
+}
@@ -0,0 +1,223 @@
+use super::*;
+use std::collections::HashSet;
+
+pub(crate) struct SourceFn<F>(pub(crate) F);
+impl<F, I, O> TaskFn<I, O> for SourceFn<F>
+where
+    F: for<'a> Fn(I::Data<'a>) -> Option<O> + Send + Sync,
+    I: Args,
+{
+    fn call(&self, args: I::Data<'_>) -> Result<O, PollError> {
+        (self.0)(args).ok_or(PollError::Closed)
+    }
+    fn is_source(&self) -> bool {
+        true
+    }
+    fn kind(&self) -> TaskKind {
+        TaskKind::Source
+    }
+}
+
+impl Plan {
+    /// Adds a task that produces a stream of items, one per poll, until it returns
+    /// `None`. Under [`Schedule::stream`] it's polled for as long as it keeps producing;
+    /// under `execute` it runs once, like any other task, and a `None` just means its
+    /// consumers have nothing to run on.
+    pub fn add_source<F, I, O, D>(&mut self, handles: I, f: F) -> TaskHandle<O>
+    where
+        F: for<'a> Fn(I::Data<'a>) -> Option<O> + Send + Sync + 'static,
+        O: Send + Sync + 'static,
+        I: Args<Receivers = D> + 'static,
+        D: ArgsState,
+    {
+        self.add_task_node(handles, SourceFn(f))
+    }
+
+    /// Runs the graph as a stream. Sources are polled until they run dry, and a task
+    /// with channel inputs fires each time all of them have a value. Everything else
+    /// runs once. A task's stream ends when a channel it takes input from has been
+    /// closed - its producer's stream ended, or the channel itself was - and emptied.
+    /// The stream as a whole ends once nothing can fire any more, and no [`Inlet`] or
+    /// [`Outlet`] that could change that is still open.
+    ///
+    /// A task that fails is closed, as if its stream had ended; under
+    /// [`FailurePolicy::Cancel`] the whole stream stops. Either way the first failure is
+    /// returned, and whatever's still queued when the stream ends is dropped.
+    pub(crate) fn run_stream(&self, compiled: &Compiled) -> Result<(), ExecutionError> {
+        let mut failures = Failures::default();
+        let mut closed = HashSet::new();
+        while !failures.cancelled {
+            let generation = self.wake.generation();
+            let mut progress = false;
+            for &node in &compiled.order {
+                if failures.cancelled {
+                    break;
+                }
+                if closed.contains(&node) {
+                    continue;
+                }
+                let streaming = self.streaming(node);
+                let polled = match self.poll_task(compiled, node) {
+                    Ok(polled) => polled,
+                    Err(e) => {
+                        self.fail(&mut failures, node, e);
+                        closed.insert(node);
+                        continue;
+                    }
+                };
+                match polled {
+                    Polled::Ran => {
+                        self.close_loops(compiled, node, &mut vec![], &mut failures);
+                        if !streaming {
+                            closed.insert(node);
+                        }
+                    }
+                    Polled::Held if !self.exhausted(node, &closed) => continue,
+                    Polled::Held | Polled::Closed => {
+                        closed.insert(node);
+                    }
+                }
+                progress = true;
+            }
+            if !progress {
+                // Nothing can fire until something changes on the other end of an inlet
+                // or outlet - if there are any left open.
+                let waiting = compiled.order.iter().any(|node| {
+                    matches!(&self.graph[*node], Node::Task(task) if task.external())
+                        && !closed.contains(node)
+                });
+                if !waiting {
+                    break;
+                }
+                self.wake.wait(generation);
+            }
+        }
+        for node in &compiled.order {
+            if let Node::Task(task) = &self.graph[*node] {
+                task.end_stream();
+            }
+        }
+        self.discard_outputs(&compiled.order, true);
+        self.finish_run(compiled, &mut failures)
+    }
+
+    /// Whether the task keeps firing for as long as it has input.
+    fn streaming(&self, node: NodeIndex) -> bool {
+        let Node::Task(task) = &self.graph[node] else {
+            unreachable!("Only tasks are scheduled");
+        };
+        task.is_source()
+            || self
+                .graph
+                .edges_directed(node, petgraph::Direction::Incoming)
+                .any(|edge| matches!(edge.weight().meta, Access::Consume | Access::Share))
+    }
+
+    /// Whether a channel input of `node` is closed and empty, so it'll never fire again.
+    fn exhausted(&self, node: NodeIndex, closed: &HashSet<NodeIndex>) -> bool {
+        let Node::Task(task) = &self.graph[node] else {
+            unreachable!("Only tasks are scheduled");
+        };
+        task.inputs_closed()
+            || self
+                .graph
+                .edges_directed(node, petgraph::Direction::Incoming)
+                .filter(|edge| matches!(edge.weight().meta, Access::Consume | Access::Share))
+                .any(|edge| {
+                    let Node::Task(producer) = &self.graph[edge.source()] else {
+                        unreachable!("Channels come from tasks");
+                    };
+                    closed.contains(&edge.source()) && producer.drained(node)
+                })
+    }
+}
+
+impl Schedule {
+    /// Runs the graph as a stream until its sources run dry, see [`Plan::add_source`].
+    pub fn stream(&mut self) -> Result<(), ExecutionError> {
+        self.plan.run_stream(&self.compiled)
+    }
+}
+
+impl Executor {
+    /// Runs the graph as a stream until its sources run dry, see [`Plan::add_source`].
+    pub fn stream(&mut self) -> Result<(), ExecutionError> {
+        let (plan, compiled) = self.compile()?;
+        plan.run_stream(compiled)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn counter(to: u32) -> impl Fn(()) -> Option<u32> + Send + Sync {
+        let next = Mutex::new(1..=to);
+        move |_| next.lock().unwrap().next()
+    }
+
+    #[test]
+    fn test_stream_to_sink() {
+        let mut graph = Executor::new();
+        let sink = graph.add_resource(vec![]);
+        let setup = graph.add_task(Write(sink), |mut sink| sink.push(0));
+        let numbers = graph.add_source((), counter(5));
+        let doubled = graph.add_task(numbers, |n| n * 2);
+        graph.add_task((doubled, Write(sink).after(setup)), |(n, mut sink)| {
+            sink.push(n)
+        });
+        graph.stream().unwrap();
+        assert_eq!(*graph.get(sink).unwrap(), [0, 2, 4, 6, 8, 10]);
+        // The source stays dry, so the next stream only runs the setup.
+        graph.stream().unwrap();
+        assert_eq!(graph.get(sink).unwrap().len(), 7);
+    }
+
+    #[test]
+    fn test_stream_ends_with_shortest_input() {
+        let mut graph = Executor::new();
+        let sink = graph.add_resource(vec![]);
+        let short = graph.add_source((), counter(3));
+        let long = graph.add_source((), counter(100));
+        // A tight channel throttles the long source to the pace of its consumer.
+        let channel = Channel {
+            capacity: 1,
+            overflow: Overflow::Block,
+        };
+        graph.add_task(
+            (short, Buffered(long, channel), Write(sink)),
+            |(a, b, mut sink)| sink.push(a * 10 + b),
+        );
+        graph.stream().unwrap();
+        assert_eq!(*graph.get(sink).unwrap(), [11, 22, 33]);
+        // Whatever the long source had queued was dropped at the end of the stream.
+        graph.execute().unwrap();
+        assert_eq!(graph.get(sink).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_stream_failure_cancels() {
+        #[derive(Debug)]
+        struct Odd;
+        impl std::fmt::Display for Odd {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "odd")
+            }
+        }
+        impl std::error::Error for Odd {}
+
+        let mut graph = Executor::new();
+        let sink = graph.add_resource(vec![]);
+        let numbers = graph.add_source((), counter(10));
+        let even = graph.add_fallible_task(numbers, |n| match n {
+            n if n > 1 && n % 2 == 1 => Err(Odd),
+            n => Ok(n),
+        });
+        graph.add_task((even, Write(sink)), |(n, mut sink)| sink.push(n));
+        assert!(matches!(
+            graph.stream(),
+            Err(ExecutionError::TaskFailed { .. })
+        ));
+        assert_eq!(*graph.get(sink).unwrap(), [1, 2]);
+    }
+}
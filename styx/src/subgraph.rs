@@ -0,0 +1,154 @@
+use super::*;
+
+/// A reusable piece of graph. Its input ports `I` are the handles it's wired to -
+/// typically a tuple of resources and task outputs - and its output ports `O` are the
+/// handles it gives back. Each [`Plan::instantiate`] adds a fresh copy of its tasks and
+/// resources, so the same subgraph can run over different resources side by side.
+pub struct Subgraph<I, O> {
+    build: Arc<Build<I, O>>,
+}
+type Build<I, O> = dyn Fn(&mut Plan, I) -> O + Send + Sync;
+impl<I, O> Subgraph<I, O> {
+    /// `build` adds the subgraph's nodes to the plan it's instantiated into, wired to
+    /// the input ports it's given. Handles it gets from anywhere other than its ports
+    /// or the plan it's handed won't point into that plan.
+    pub fn new(build: impl Fn(&mut Plan, I) -> O + Send + Sync + 'static) -> Self {
+        Self {
+            build: Arc::new(build),
+        }
+    }
+}
+impl<I, O> Clone for Subgraph<I, O> {
+    fn clone(&self) -> Self {
+        Self {
+            build: self.build.clone(),
+        }
+    }
+}
+
+/// A subgraph being instantiated: its name, and the nodes it's added so far.
+pub(crate) struct Scope {
+    name: String,
+    pub(crate) nodes: HashSet<NodeIndex>,
+}
+
+impl Plan {
+    /// Adds a copy of `subgraph`, wired to `inputs`. Labels it sets on its own nodes are
+    /// prefixed with `name/`, as are the defaults of nodes it leaves unlabelled, so they
+    /// can be told apart from other instances in errors, traces and exported graphs.
+    /// Instances nest, giving labels like `world/left/integrate`.
+    pub fn instantiate<I, O>(
+        &mut self,
+        name: impl Into<String>,
+        subgraph: &Subgraph<I, O>,
+        inputs: I,
+    ) -> O {
+        self.scopes.push(Scope {
+            name: name.into(),
+            nodes: HashSet::new(),
+        });
+        let outputs = (subgraph.build)(self, inputs);
+        let nodes = &self.scopes.last().unwrap().nodes;
+        let unlabelled: Vec<_> = nodes
+            .iter()
+            .copied()
+            .filter(|node| self.graph.contains_node(*node) && !self.labels.contains_key(node))
+            .collect();
+        for node in unlabelled {
+            let label = self.display_label(node);
+            self.insert_label(node, label);
+        }
+        self.scopes.pop();
+        outputs
+    }
+
+    /// `label`, prefixed with the names of the subgraphs being instantiated that `node`
+    /// belongs to. Ports handed in from outside keep their labels as they are.
+    pub(crate) fn scoped_label(&self, node: NodeIndex, label: String) -> String {
+        let mut scoped = String::new();
+        for scope in self.scopes.iter().filter(|s| s.nodes.contains(&node)) {
+            scoped.push_str(&scope.name);
+            scoped.push('/');
+        }
+        scoped + &label
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    type Bodies = ResourceHandle<Vec<f32>>;
+
+    /// Moves bodies by their velocities, and reports their kinetic energy. Gives back
+    /// the integrating task and the one reporting energy.
+    fn physics_step() -> Subgraph<(Bodies, Bodies), (TaskHandle<()>, TaskHandle<f32>)> {
+        Subgraph::new(|plan, (positions, velocities): (Bodies, Bodies)| {
+            let steps = plan.add_resource(0u32);
+            let integrate = plan.add_task(
+                (Write(positions), Read(velocities), Write(steps)),
+                |(mut p, v, mut steps)| {
+                    p.iter_mut().zip(v.iter()).for_each(|(p, v)| *p += v);
+                    *steps += 1;
+                },
+            );
+            plan.set_label(integrate, "integrate");
+            let energy = plan.add_task(Read(velocities).after(integrate), |v| {
+                v.iter().map(|v| v * v / 2.0).sum()
+            });
+            (integrate, energy)
+        })
+    }
+
+    #[test]
+    fn test_instantiate_twice() {
+        let mut graph = Executor::new();
+        let step = physics_step();
+        let left = (graph.add_resource(vec![0.0]), graph.add_resource(vec![1.0]));
+        let right = (
+            graph.add_resource(vec![0.0; 2]),
+            graph.add_resource(vec![2.0; 2]),
+        );
+        graph.set_label(left.0, "positions");
+        let (left_integrate, left_energy) = graph.instantiate("left", &step, left);
+        let (_, right_energy) = graph.instantiate("right", &step, right);
+        graph.execute_parallel().unwrap();
+        graph.execute().unwrap();
+        assert_eq!(*graph.get(left.0).unwrap(), [2.0]);
+        assert_eq!(*graph.get(right.0).unwrap(), [4.0, 4.0]);
+        assert_eq!(*graph.output(left_energy).unwrap(), 0.5);
+        assert_eq!(*graph.output(right_energy).unwrap(), 4.0);
+
+        assert_eq!(graph.label(left_integrate), Some("left/integrate"));
+        assert_eq!(
+            graph.label(left_energy),
+            Some(&*format!("left/task {}", left_energy.idx.index()))
+        );
+        assert_eq!(graph.label(left.0), Some("positions"));
+        assert!(graph.to_dot().contains("right/integrate"));
+    }
+
+    #[test]
+    fn test_nested_labels_in_errors() {
+        let mut plan = Plan::new();
+        let fail = Subgraph::new(|plan, input: TaskHandle<i32>| {
+            let task = plan.add_fallible_task(input, |v| {
+                Err::<(), _>(std::io::Error::other(format!("bad {v}")))
+            });
+            plan.set_label(task, "check");
+            task
+        });
+        let world = Subgraph::new(move |plan, ()| {
+            let source = plan.add_task((), |()| 1);
+            (source, plan.instantiate("inner", &fail, source))
+        });
+        let (source, check) = plan.instantiate("world", &world, ());
+        assert_eq!(plan.label(check), Some("world/inner/check"));
+        let mut schedule = plan.build().unwrap();
+        let Err(ExecutionError::TaskFailed { label, .. }) = schedule.execute() else {
+            panic!("The task didn't fail");
+        };
+        assert_eq!(label.as_deref(), Some("world/inner/check"));
+        assert_eq!(schedule.label(source), Some("world/task 0"));
+    }
+}
@@ -0,0 +1,384 @@
+use super::*;
+
+/// Which `add_*` a task was added with. A task can only be replaced by one of the same
+/// kind, so it keeps how it's run and how its failures and streams are handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TaskKind {
+    Plain,
+    Async,
+    Fallible,
+    Pure,
+    Source,
+    /// An inlet or outlet, tied to its other end.
+    External,
+}
+
+impl Plan {
+    /// Swaps a task's function for `f`, and its inputs for `handles` - pass the same
+    /// handles to only change what it does, or others to rewire it. The task keeps its
+    /// handle, its label and its consumers; outputs queued for it are dropped. Like any
+    /// other change to the graph, an [`Executor`] validates and recompiles it before the
+    /// next run.
+    ///
+    /// The new function has to be of the same kind as the old one, so this only replaces
+    /// tasks added with [`Plan::add_task`]; the others have their own `replace_*`. Inlets
+    /// and outlets can't be replaced. Panics on a mismatch, or if the task's been removed.
+    pub fn replace_task<F, I, O, D>(&mut self, task: TaskHandle<O>, handles: I, f: F)
+    where
+        F: for<'a> Fn(I::Data<'a>) -> O + Send + Sync + 'static,
+        O: Send + Sync + 'static,
+        I: Args<Receivers = D> + 'static,
+        D: ArgsState,
+    {
+        self.replace_task_node(task, handles, SyncFn(f));
+    }
+
+    /// Like [`Plan::replace_task`], for tasks added with [`Plan::add_fallible_task`].
+    pub fn replace_fallible_task<F, I, O, E, D>(&mut self, task: TaskHandle<O>, handles: I, f: F)
+    where
+        F: for<'a> Fn(I::Data<'a>) -> Result<O, E> + Send + Sync + 'static,
+        O: Send + Sync + 'static,
+        E: std::error::Error + Send + Sync + 'static,
+        I: Args<Receivers = D> + 'static,
+        D: ArgsState,
+    {
+        self.replace_task_node(task, handles, FallibleFn(f));
+    }
+
+    /// Like [`Plan::replace_task`], for tasks added with [`Plan::add_pure_task`].
+    pub fn replace_pure_task<F, I, O, D>(&mut self, task: TaskHandle<O>, handles: I, f: F)
+    where
+        F: for<'a> Fn(I::Data<'a>) -> O + Send + Sync + 'static,
+        O: Send + Sync + 'static,
+        I: Args<Receivers = D> + 'static,
+        D: ArgsState,
+    {
+        memo::assert_pure(&handles);
+        self.replace_task_node(task, handles, PureFn(f));
+    }
+
+    /// Like [`Plan::replace_task`], for tasks added with [`Plan::add_async_task`].
+    pub fn replace_async_task<F, I, O, D>(&mut self, task: TaskHandle<O>, handles: I, f: F)
+    where
+        F: for<'a> Fn(Inputs<'a, I>) -> BoxFuture<'a, O> + Send + Sync + 'static,
+        O: Send + Sync + 'static,
+        I: Args<Receivers = D> + 'static,
+        D: ArgsState,
+    {
+        let runtime = self.runtime();
+        self.replace_task_node(task, handles, AsyncFn { f, runtime });
+    }
+
+    /// Like [`Plan::replace_task`], for tasks added with [`Plan::add_source`] or
+    /// [`Plan::add_iter_source`].
+    pub fn replace_source<F, I, O, D>(&mut self, task: TaskHandle<O>, handles: I, f: F)
+    where
+        F: for<'a> Fn(I::Data<'a>) -> Option<O> + Send + Sync + 'static,
+        O: Send + Sync + 'static,
+        I: Args<Receivers = D> + 'static,
+        D: ArgsState,
+    {
+        self.replace_task_node(task, handles, SourceFn(f));
+    }
+
+    fn replace_task_node<T, I, O, D>(&mut self, task: TaskHandle<O>, handles: I, f: T)
+    where
+        T: TaskFn<I, O> + 'static,
+        O: Send + Sync + 'static,
+        I: Args<Receivers = D> + 'static,
+        D: ArgsState,
+    {
+        let Some(kind) = self.task_of(task).map(TaskNode::kind) else {
+            panic!("Only tasks still in the graph can be replaced");
+        };
+        assert!(
+            kind == f.kind() && kind != TaskKind::External,
+            "A {kind:?} task can't be replaced with a {:?} one",
+            f.kind()
+        );
+        self.check_args(&handles);
+        let Some(Node::Task(old)) = self.graph.node_weight_mut(task.idx) else {
+            unreachable!("The task was just found");
+        };
+        let outgoing = *old
+            .take_outgoing()
+            .downcast::<Vec<Outgoing<Arc<O>>>>()
+            .expect("Task scheduled with incorrect arguments. CRITICAL LIBRARY BUG");
+        self.disconnect(task.idx);
+        self.orphans.remove(&task.idx);
+        self.connect_task(task.idx, handles, f, outgoing);
+    }
+
+    /// Removes a task, along with its edges and channels. Tasks that took its output are
+    /// left without an input, which fails validation until they're replaced or removed
+    /// as well. Returns false if the task was already gone.
+    pub fn remove_task<T>(&mut self, task: TaskHandle<T>) -> bool {
+        self.task_of(task).is_some() && self.remove_node(task.idx).is_some()
+    }
+
+    /// Removes a resource and hands back its data, poisoned or not. Tasks that took it
+    /// fail validation until they're replaced or removed as well.
+    pub fn remove_resource<T: 'static>(&mut self, resource: ResourceHandle<T>) -> Option<T> {
+        self.resource_of(resource)?;
+        let Node::Resource(resource) = self.remove_node(resource.idx)? else {
+            return None;
+        };
+        resource.into_inner().downcast().ok().map(|data| *data)
+    }
+
+    fn remove_node(&mut self, node: NodeIndex) -> Option<Node> {
+        if !self.graph.contains_node(node) {
+            return None;
+        }
+        self.disconnect(node);
+        for edge in self
+            .graph
+            .edges_directed(node, petgraph::Direction::Outgoing)
+        {
+            if edge.weight().meta != Access::Order {
+                self.orphans.insert(edge.target());
+            }
+        }
+        self.orphans.remove(&node);
+        self.labels.remove(&node);
+        self.codecs.remove(&node);
+        self.output_codecs.remove(&node);
+        if let Some(tracer) = &self.tracer {
+            tracer.forget(node);
+        }
+        if let Some(recorder) = &self.recorder {
+            recorder.forget(node);
+        }
+        for scope in &mut self.scopes {
+            scope.nodes.remove(&node);
+        }
+        self.feedback
+            .retain(|feedback| feedback.from != node && feedback.to != node);
+        *self.generations.entry(node).or_default() += 1;
+        self.graph.remove_node(node)
+    }
+
+    /// How many nodes at `node`'s index have been removed. Handles remember it, so one
+    /// made before a removal doesn't point at whatever's added in the node's place.
+    pub(crate) fn generation(&self, node: NodeIndex) -> u32 {
+        self.generations.get(&node).copied().unwrap_or_default()
+    }
+
+    /// Whether the node `handle` was handed out for is still in the graph.
+    pub(crate) fn contains(&self, handle: NodeHandle) -> bool {
+        self.graph.contains_node(handle.idx) && self.generation(handle.idx) == handle.generation
+    }
+
+    /// The resource `handle` points at, unless it's been removed.
+    pub(crate) fn resource_of<T>(&self, handle: ResourceHandle<T>) -> Option<&Resource> {
+        match self.graph.node_weight(handle.idx) {
+            Some(Node::Resource(resource)) if self.generation(handle.idx) == handle.generation => {
+                Some(resource)
+            }
+            _ => None,
+        }
+    }
+
+    /// The task `handle` points at, unless it's been removed.
+    pub(crate) fn task_of<T>(&self, handle: TaskHandle<T>) -> Option<&dyn TaskNode> {
+        match self.graph.node_weight(handle.idx) {
+            Some(Node::Task(task)) if self.generation(handle.idx) == handle.generation => {
+                Some(&**task)
+            }
+            _ => None,
+        }
+    }
+
+    /// Drops the edges into `node`, and the channels its producers opened to it.
+    fn disconnect(&mut self, node: NodeIndex) {
+        let incoming: Vec<_> = self
+            .graph
+            .edges_directed(node, petgraph::Direction::Incoming)
+            .map(|edge| (edge.id(), edge.source()))
+            .collect();
+        for (edge, producer) in incoming {
+            if let Node::Task(task) = &mut self.graph[producer] {
+                task.disconnect(node);
+            }
+            self.graph.remove_edge(edge);
+        }
+    }
+
+    /// Fails if a task still expects input from a node that's been removed.
+    pub(crate) fn check_inputs(&self) -> Result<(), ExecutionError> {
+        match self.orphans.iter().min() {
+            Some(&task) => Err(ExecutionError::MissingInput {
+                task,
+                label: self.labels.get(&task).cloned(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::panic::AssertUnwindSafe;
+
+    #[test]
+    fn test_toggle_debug_task() {
+        let mut graph = Executor::new();
+        let value = graph.add_resource(1);
+        let log = graph.add_resource(vec![]);
+        let double = graph.add_task(Read(value), |v| *v * 2);
+        let debug = graph.add_task((double, Write(log)), |(v, mut log)| log.push(v));
+        graph.execute().unwrap();
+        graph.replace_task(debug, (double, Write(log)), |(v, mut log)| log.push(-v));
+        graph.execute_parallel().unwrap();
+        assert!(graph.remove_task(debug));
+        assert!(!graph.remove_task(debug));
+        graph.execute().unwrap();
+        assert_eq!(*graph.get(log).unwrap(), [2, -2]);
+        // Nothing takes the output any more, so it's kept rather than lent out.
+        assert_eq!(*graph.output(double).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_rewire_task() {
+        let mut graph = Executor::new();
+        let a = graph.add_resource(1);
+        let b = graph.add_resource(10);
+        let from_a = graph.add_task(Read(a), |v| *v);
+        let from_b = graph.add_task(Read(b), |v| *v);
+        let sum = graph.add_task(from_a, |v| v + 100);
+        graph.execute().unwrap();
+        assert_eq!(*graph.output(sum).unwrap(), 101);
+        graph.replace_task(sum, (from_a, from_b), |(a, b)| a + b);
+        graph.execute_parallel().unwrap();
+        assert_eq!(*graph.output(sum).unwrap(), 11);
+    }
+
+    #[test]
+    fn test_removed_input_fails_validation() {
+        let mut graph = Executor::new();
+        let value = graph.add_resource(3);
+        let producer = graph.add_task((), |()| 1);
+        let consumer = graph.add_task((producer, Read(value)), |(a, b)| a + *b);
+        graph.set_label(consumer, "consumer");
+        assert_eq!(graph.remove_resource(value), Some(3));
+        let Err(ExecutionError::MissingInput { task, label }) = graph.execute() else {
+            panic!("The missing input wasn't caught");
+        };
+        assert_eq!((task, label.as_deref()), (consumer.idx, Some("consumer")));
+        graph.replace_task(consumer, producer, |a| a + 1);
+        graph.execute().unwrap();
+        assert_eq!(*graph.output(consumer).unwrap(), 2);
+
+        graph.remove_task(producer);
+        assert!(graph.execute().is_err());
+        graph.remove_task(consumer);
+        graph.execute().unwrap();
+        assert!(graph.output(consumer).is_none());
+    }
+
+    #[test]
+    fn test_replace_fallible_task() {
+        let mut graph = Executor::new();
+        let value = graph.add_resource(3);
+        let check = graph.add_fallible_task(Read(value), |v| Ok::<_, std::io::Error>(*v));
+        graph.replace_fallible_task(check, Read(value), |v| match *v {
+            0..=2 => Ok(*v),
+            _ => Err(std::io::Error::other("too big")),
+        });
+        let Err(ExecutionError::TaskFailed { task, error, .. }) = graph.execute() else {
+            panic!("The failure didn't come through");
+        };
+        assert_eq!(
+            (task, error.to_string()),
+            (check.idx, "too big".to_string())
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_replace_rejects_other_kinds() {
+        let mut graph = Executor::new();
+        let task = graph.add_pure_task((), |()| 1);
+        graph.replace_task(task, (), |()| 2);
+    }
+
+    #[test]
+    fn test_stale_handles() {
+        let mut graph = Executor::new();
+        let a = graph.add_resource(1);
+        let task = graph.add_task((), |()| 5);
+        assert_eq!(graph.remove_resource(a), Some(1));
+        assert!(graph.remove_task(task));
+        // The new nodes take the removed ones' indices.
+        let other = graph.add_task((), |()| 6);
+        let b = graph.add_resource(99);
+        assert_eq!((b.idx, other.idx), (a.idx, task.idx));
+        graph.execute().unwrap();
+        assert!(graph.get(a).is_none());
+        assert!(graph.get_mut(a).is_none());
+        assert!(graph.output(task).is_none());
+        assert_eq!(graph.remove_resource(a), None);
+        assert!(!graph.remove_task(task));
+        assert_eq!(*graph.get(b).unwrap(), 99);
+        assert_eq!(*graph.output(other).unwrap(), 6);
+    }
+
+    #[test]
+    fn test_removed_task_forgotten() {
+        let mut graph = Executor::new();
+        graph.enable_tracing();
+        graph.start_recording();
+        let kept = graph.add_task((), |()| 1);
+        let removed = graph.add_labelled_task("doomed", (), |()| 2);
+        graph.execute().unwrap();
+        graph.remove_task(removed);
+        assert!(!graph.to_chrome_trace().contains("doomed"));
+        assert!(graph.spans().iter().all(|span| span.task != removed.idx));
+        let replacement = graph.add_task((), |()| 3);
+        assert_eq!(replacement.idx, removed.idx);
+        assert_eq!(graph.label(replacement), None);
+        let recording = graph.stop_recording();
+        let polled: Vec<_> = recording.runs[0]
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Poll { task, .. } => Some(*task),
+                Event::Discard { .. } => None,
+            })
+            .collect();
+        assert_eq!(polled, [kept.idx.index()]);
+    }
+
+    #[test]
+    fn test_stale_handles_as_arguments() {
+        let mut graph = Executor::new();
+        let a = graph.add_resource(1);
+        let task = graph.add_task((), |()| 5);
+        graph.remove_resource(a);
+        graph.remove_task(task);
+        let other = graph.add_task((), |()| 6);
+        let b = graph.add_resource(99);
+        assert_eq!((b.idx, other.idx), (a.idx, task.idx));
+        let mut rejected = |f: &mut dyn FnMut(&mut Executor)| {
+            std::panic::catch_unwind(AssertUnwindSafe(|| f(&mut graph))).is_err()
+        };
+        assert!(rejected(&mut |graph| {
+            graph.add_task(Read(a), |v| *v);
+        }));
+        assert!(rejected(&mut |graph| {
+            graph.add_task(().after(task), |()| ());
+        }));
+        assert!(rejected(&mut |graph| {
+            graph.replace_task(other, task, |v| v);
+        }));
+        assert!(rejected(&mut |graph| graph.set_label(a, "a")));
+        assert!(rejected(&mut |graph| graph.record_output(task)));
+        assert!(rejected(&mut |graph| {
+            graph.add_feedback_edge_until(task, other, 2, |_| false)
+        }));
+        graph.execute().unwrap();
+        assert_eq!(graph.label(b), None);
+        assert_eq!(*graph.output(other).unwrap(), 6);
+    }
+}
@@ -0,0 +1,287 @@
+use super::*;
+use serde_json::json;
+use std::{
+    sync::atomic::AtomicUsize,
+    time::{Duration, Instant},
+};
+
+/// What a [`Span`] covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SpanKind {
+    /// A whole poll of the task.
+    Poll,
+    /// Taking the task's inputs: receiving from its channels and locking its resources.
+    Acquire,
+    /// Sending the task's output to its consumers.
+    Send,
+    /// Under `execute_parallel`, the time a task spent with its dependencies met, queued
+    /// for leases on its resources.
+    Wait,
+}
+impl SpanKind {
+    fn name(self) -> &'static str {
+        match self {
+            SpanKind::Poll => "poll",
+            SpanKind::Acquire => "acquire",
+            SpanKind::Send => "send",
+            SpanKind::Wait => "wait",
+        }
+    }
+}
+
+/// A stretch of time a task spent on something, measured from when tracing started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub task: NodeIndex,
+    pub kind: SpanKind,
+    /// The thread it happened on, numbered in the order threads were first traced.
+    /// [`SpanKind::Wait`] spans don't happen on any thread.
+    pub thread: Option<usize>,
+    pub start: Duration,
+    pub duration: Duration,
+}
+
+/// Per-task statistics over every poll traced so far.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskSummary {
+    pub task: NodeIndex,
+    pub label: Option<String>,
+    pub count: usize,
+    pub total: Duration,
+    pub mean: Duration,
+    pub p99: Duration,
+    /// Time spent waiting on leases and taking inputs.
+    pub blocked: Duration,
+}
+
+static NEXT_THREAD: AtomicUsize = AtomicUsize::new(0);
+thread_local! {
+    static THREAD: usize = NEXT_THREAD.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) struct Tracer {
+    epoch: Instant,
+    spans: Mutex<Vec<Span>>,
+    threads: Mutex<HashMap<usize, String>>,
+}
+impl Tracer {
+    fn new() -> Self {
+        Self {
+            epoch: Instant::now(),
+            spans: Mutex::new(vec![]),
+            threads: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records a span from `start` until now, on the current thread unless it's a wait.
+    pub(crate) fn record(&self, task: NodeIndex, kind: SpanKind, start: Instant) {
+        let thread = (kind != SpanKind::Wait).then(|| {
+            let thread = THREAD.with(|thread| *thread);
+            self.threads.lock().unwrap().entry(thread).or_insert_with(
+                || match std::thread::current().name() {
+                    Some(name) => name.to_string(),
+                    None => format!("thread {thread}"),
+                },
+            );
+            thread
+        });
+        self.spans.lock().unwrap().push(Span {
+            task,
+            kind,
+            thread,
+            start: start.saturating_duration_since(self.epoch),
+            duration: start.elapsed(),
+        });
+    }
+
+    /// Drops the spans of a removed task, so they aren't put down to whatever takes its
+    /// index next.
+    pub(crate) fn forget(&self, task: NodeIndex) {
+        self.spans.lock().unwrap().retain(|span| span.task != task);
+    }
+}
+
+impl<'a> RwGuards<'a> {
+    pub(crate) fn trace(&self, kind: SpanKind, start: Instant) {
+        if let Some(tracer) = self.tracer {
+            tracer.record(self.task, kind, start);
+        }
+    }
+}
+
+impl Plan {
+    /// Starts recording a [`Span`] for every task poll, and the parts of it spent taking
+    /// inputs and sending outputs. Spans pile up until they're cleared.
+    pub fn enable_tracing(&mut self) {
+        self.tracer.get_or_insert_with(Tracer::new);
+    }
+    pub fn disable_tracing(&mut self) {
+        self.tracer = None;
+    }
+    pub fn clear_trace(&self) {
+        if let Some(tracer) = &self.tracer {
+            tracer.spans.lock().unwrap().clear();
+        }
+    }
+    pub fn spans(&self) -> Vec<Span> {
+        match &self.tracer {
+            Some(tracer) => tracer.spans.lock().unwrap().clone(),
+            None => vec![],
+        }
+    }
+
+    /// Summarises the trace per task, slowest in total first.
+    pub fn trace_summary(&self) -> Vec<TaskSummary> {
+        let mut polls: HashMap<NodeIndex, Vec<Duration>> = HashMap::new();
+        let mut blocked: HashMap<NodeIndex, Duration> = HashMap::new();
+        for span in self.spans() {
+            match span.kind {
+                SpanKind::Poll => polls.entry(span.task).or_default().push(span.duration),
+                SpanKind::Acquire | SpanKind::Wait => {
+                    *blocked.entry(span.task).or_default() += span.duration
+                }
+                SpanKind::Send => {}
+            }
+        }
+        let mut summary: Vec<_> = polls
+            .into_iter()
+            .map(|(task, mut durations)| {
+                durations.sort_unstable();
+                let count = durations.len();
+                let total = durations.iter().sum::<Duration>();
+                TaskSummary {
+                    task,
+                    label: self.labels.get(&task).cloned(),
+                    count,
+                    total,
+                    mean: total / count as u32,
+                    p99: durations[(count * 99).div_ceil(100) - 1],
+                    blocked: blocked.get(&task).copied().unwrap_or_default(),
+                }
+            })
+            .collect();
+        summary.sort_unstable_by(|a, b| b.total.cmp(&a.total).then(a.task.cmp(&b.task)));
+        summary
+    }
+
+    /// Writes the trace in the Chrome `trace_event` JSON format, which Perfetto and
+    /// `chrome://tracing` load. Each thread gets a track; waits for leases are drawn as
+    /// async slices, since they don't occupy a thread.
+    pub fn to_chrome_trace(&self) -> String {
+        let mut events = vec![];
+        if let Some(tracer) = &self.tracer {
+            let mut threads: Vec<_> = tracer.threads.lock().unwrap().clone().into_iter().collect();
+            threads.sort_unstable();
+            for (thread, name) in threads {
+                events.push(json!({
+                    "name": "thread_name",
+                    "ph": "M",
+                    "pid": 1,
+                    "tid": thread,
+                    "args": { "name": name },
+                }));
+            }
+        }
+        for (id, span) in self.spans().into_iter().enumerate() {
+            let name = self.display_label(span.task);
+            let category = span.kind.name();
+            let args = json!({ "task": span.task.index() });
+            let start = span.start.as_nanos() as f64 / 1000.0;
+            let duration = span.duration.as_nanos() as f64 / 1000.0;
+            match span.thread {
+                Some(thread) => events.push(json!({
+                    "name": name,
+                    "cat": category,
+                    "ph": "X",
+                    "ts": start,
+                    "dur": duration,
+                    "pid": 1,
+                    "tid": thread,
+                    "args": args,
+                })),
+                None => {
+                    events.push(json!({
+                        "name": name,
+                        "cat": category,
+                        "ph": "b",
+                        "id": id,
+                        "ts": start,
+                        "pid": 1,
+                        "args": args,
+                    }));
+                    events.push(json!({
+                        "name": name,
+                        "cat": category,
+                        "ph": "e",
+                        "id": id,
+                        "ts": start + duration,
+                        "pid": 1,
+                    }));
+                }
+            }
+        }
+        json!({ "traceEvents": events }).to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_trace_summary() {
+        let mut graph = Executor::new();
+        graph.enable_tracing();
+        let value = graph.add_resource(1);
+        let slow = graph.add_task(Read(value), |v| {
+            std::thread::sleep(Duration::from_millis(2));
+            *v
+        });
+        graph.set_label(slow, "slow");
+        let fast = graph.add_task(slow, |v| v + 1);
+        for _ in 0..3 {
+            graph.execute().unwrap();
+            graph.execute_parallel().unwrap();
+        }
+        let summary = graph.trace_summary();
+        assert_eq!(summary.len(), 2);
+        assert_eq!(summary[0].label.as_deref(), Some("slow"));
+        assert_eq!((summary[0].count, summary[1].count), (6, 6));
+        assert!(summary[0].mean >= Duration::from_millis(2));
+        assert!(summary[0].p99 >= summary[0].mean);
+        assert_eq!(summary[1].task, fast.idx);
+
+        let spans = graph.spans();
+        assert!(spans.iter().any(|span| span.kind == SpanKind::Wait));
+        assert!(spans
+            .iter()
+            .filter(|span| span.kind != SpanKind::Wait)
+            .all(|span| span.thread.is_some()));
+        graph.clear_trace();
+        assert!(graph.trace_summary().is_empty());
+    }
+
+    #[test]
+    fn test_chrome_trace_export() {
+        let mut graph = Executor::new();
+        graph.enable_tracing();
+        let quoted = graph.add_task((), |()| 1);
+        graph.set_label(quoted, "say \"hi\"");
+        graph.add_task(quoted, |v| v);
+        graph.execute_parallel().unwrap();
+        let trace: serde_json::Value = serde_json::from_str(&graph.to_chrome_trace()).unwrap();
+        let events = trace["traceEvents"].as_array().unwrap();
+        let has = |name: &str, category: &str, phase: &str| {
+            events.iter().any(|event| {
+                (event["name"] == name || name.is_empty())
+                    && (event["cat"] == category || category.is_empty())
+                    && event["ph"] == phase
+            })
+        };
+        assert!(has("thread_name", "", "M"));
+        assert!(has("say \"hi\"", "poll", "X"));
+        assert!(has("", "send", "X"));
+        assert!(has("", "wait", "b"));
+        assert!(has("", "wait", "e"));
+    }
+}